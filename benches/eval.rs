@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rnix_experiments::{eval_str, print_value, EvalOptions};
+use std::hint::black_box;
+
+// A let chain where every binding depends on the previous one
+fn let_chain(len: usize) -> String {
+    let bindings = (1..len)
+        .map(|i| format!("x{} = x{} + 1;", i, i - 1))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("let x0 = 0;\n{}\nin x{}", bindings, len - 1)
+}
+
+fn large_attrset(len: usize) -> String {
+    let attrs = (0..len)
+        .map(|i| format!("a{} = {{ value = {}; name = \"a{}\"; }};", i, i, i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{{\n{}\n}}", attrs)
+}
+
+fn map_over_list(len: usize) -> String {
+    let items = (0..len).map(|i| i.to_string()).collect::<Vec<_>>();
+    format!("map (x: x * 2) [ {} ]", items.join(" "))
+}
+
+// A file that imports the same helper many times, which is only evaluated once
+fn repeated_imports(len: usize) -> String {
+    let dir = std::env::temp_dir().join("rnix-experiments-bench-imports");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("helper.nix"), large_attrset(200)).unwrap();
+    let imports = (0..len)
+        .map(|i| format!("(import {}/helper.nix).a{}.value", dir.display(), i % 200))
+        .collect::<Vec<_>>();
+    format!("[ {} ]", imports.join(" "))
+}
+
+// Evaluation is lazy, so the result is printed to force all of it
+fn bench_eval(c: &mut Criterion, name: &str, source: &str) {
+    let options = EvalOptions::default();
+    c.bench_function(name, |b| {
+        b.iter(|| print_value(&eval_str(black_box(source), &options).unwrap()).unwrap())
+    });
+}
+
+fn fixtures(c: &mut Criterion) {
+    bench_eval(c, "let chain", &let_chain(500));
+    bench_eval(c, "large attrset", &large_attrset(2000));
+    bench_eval(c, "map over a list", &map_over_list(5000));
+    bench_eval(c, "repeated imports", &repeated_imports(500));
+}
+
+criterion_group!(benches, fixtures);
+criterion_main!(benches);
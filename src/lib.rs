@@ -0,0 +1,5525 @@
+use eyre::{eyre, WrapErr};
+use phf::phf_map;
+use regex::Regex;
+use rnix::ast::{
+    Attr, BinOpKind, Entry, Expr, HasEntry, InterpolPart, LiteralKind, Param, UnaryOpKind,
+};
+use rowan::ast::AstNode;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Errors raised while evaluating. They are carried inside eyre reports, so callers can
+// recover the kind with `report.downcast_ref::<EvalError>()`
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    NotInScope(String),
+    MissingAttribute(String),
+    // An attribute of `builtins` that isn't implemented, or doesn't exist
+    UnknownBuiltin(String),
+    DuplicateAttribute(String),
+    // A value of the wrong type was used, with a message describing the mismatch
+    TypeError(String),
+    UnexpectedArgument(String),
+    MissingArgument(String),
+    // An argument of the top-level lambda that callPackage wouldn't know how to pass
+    UnknownRootArgument(String),
+    // A builtin was called with an argument it cannot handle
+    InvalidArgument(String),
+    UnsupportedExpr(&'static str),
+    // The syntax tree is missing a node, which only happens for files with parse errors
+    MalformedAst(&'static str),
+    // Raised by builtins.throw, which unlike the errors above can be caught
+    Throw(String),
+    Abort(String),
+    AssertFailure(String),
+    InfiniteRecursion,
+    DivisionByZero,
+    IntegerOverflow,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInScope(name) => write!(f, "value not in scope: {}", name),
+            Self::MissingAttribute(name) | Self::UnknownBuiltin(name) => {
+                write!(f, "attribute '{}' missing", name)
+            }
+            Self::DuplicateAttribute(name) => write!(f, "attribute '{}' already defined", name),
+            Self::TypeError(msg) | Self::InvalidArgument(msg) | Self::Throw(msg) => {
+                write!(f, "{}", msg)
+            }
+            Self::UnexpectedArgument(name) => {
+                write!(f, "function called with unexpected argument '{}'", name)
+            }
+            Self::MissingArgument(name) => {
+                write!(f, "function called without required argument '{}'", name)
+            }
+            Self::UnknownRootArgument(name) => write!(f, "unknown callPackage arg: {}", name),
+            Self::UnsupportedExpr(kind) => write!(f, "cannot eval object of type: {}", kind),
+            Self::MalformedAst(msg) => write!(f, "{}", msg),
+            Self::Abort(msg) => write!(
+                f,
+                "evaluation aborted with the following error message: '{}'",
+                msg
+            ),
+            Self::AssertFailure(condition) => write!(f, "assertion '{}' failed", condition),
+            Self::InfiniteRecursion => write!(f, "infinite recursion encountered"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::IntegerOverflow => write!(f, "integer overflow"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl EvalError {
+    /// The name of the variant, like `TypeError`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NotInScope(_) => "NotInScope",
+            Self::MissingAttribute(_) => "MissingAttribute",
+            Self::UnknownBuiltin(_) => "UnknownBuiltin",
+            Self::DuplicateAttribute(_) => "DuplicateAttribute",
+            Self::TypeError(_) => "TypeError",
+            Self::UnexpectedArgument(_) => "UnexpectedArgument",
+            Self::MissingArgument(_) => "MissingArgument",
+            Self::UnknownRootArgument(_) => "UnknownRootArgument",
+            Self::InvalidArgument(_) => "InvalidArgument",
+            Self::UnsupportedExpr(_) => "UnsupportedExpr",
+            Self::MalformedAst(_) => "MalformedAst",
+            Self::Throw(_) => "Throw",
+            Self::Abort(_) => "Abort",
+            Self::AssertFailure(_) => "AssertFailure",
+            Self::InfiniteRecursion => "InfiniteRecursion",
+            Self::DivisionByZero => "DivisionByZero",
+            Self::IntegerOverflow => "IntegerOverflow",
+        }
+    }
+}
+
+fn type_error<T>(msg: String) -> color_eyre::Result<T> {
+    Err(EvalError::TypeError(msg).into())
+}
+
+#[derive(Clone, Debug)]
+pub enum NixObject {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    // A string in which some of the store paths we made up are no longer dependencies,
+    // since unsafeDiscardStringContext dropped their context. In other strings, every
+    // such store path is part of the context
+    DiscardedStr(String, Rc<BTreeSet<String>>),
+    List(Rc<Vec<NixObject>>),
+    Set(NixSet),
+    // A value that is only evaluated once it is needed
+    Thunk(Thunk),
+    Lambda(Lambda),
+    // A builtin function along with the arguments applied to it so far
+    PrimOp(&'static PrimOp, Vec<NixObject>),
+    Nixpkg(String),
+    // A path as written in the source. It is not resolved against the file's directory
+    Path(String),
+    Source(Rc<Source>),
+    FormatFactory { format_type: String },
+    // A value we know nothing about, labelled with where it came from
+    Unknown(String),
+}
+
+impl NixObject {
+    fn lazy(f: impl Fn() -> color_eyre::Result<NixObject> + 'static) -> Self {
+        Self::Thunk(Thunk::new(None, f))
+    }
+
+    // Like lazy, but remembers that the value is defined by `expr`
+    fn lazy_at(expr: &Expr, f: impl Fn() -> color_eyre::Result<NixObject> + 'static) -> Self {
+        Self::Thunk(Thunk::new(provenance_of(expr), f))
+    }
+
+    // Defer evaluating `expr` until the value is forced
+    fn thunk(scope: &Scope, expr: Expr) -> Self {
+        let scope = scope.clone();
+        Self::lazy_at(&expr.clone(), move || eval_object(&scope, expr.clone()))
+    }
+
+    /// Where the value was defined, if it has not been forced into a container yet and
+    /// provenance tracking is enabled
+    pub fn provenance(&self) -> Option<Provenance> {
+        match self {
+            Self::Thunk(t) => t.origin,
+            _ => None,
+        }
+    }
+
+    fn from_attrs<'a>(attrs: impl IntoIterator<Item = (&'a str, NixObject)>) -> Self {
+        Self::Set(NixSet::Dyn(Rc::new(
+            attrs.into_iter().map(|(k, v)| (k.to_owned(), v)).collect(),
+        )))
+    }
+
+    // Evaluate to weak head normal form, i.e. until the outermost value is not a thunk
+    pub fn force(&self) -> color_eyre::Result<NixObject> {
+        match self {
+            Self::Thunk(t) => t.force(),
+            v => Ok(v.clone()),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool(_) => "bool",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::Str(_) | Self::DiscardedStr(..) => "string",
+            Self::List(_) => "list",
+            Self::Set(_) => "set",
+            Self::Thunk(_) => "thunk",
+            Self::Lambda(_) => "lambda",
+            Self::PrimOp(..) => "primop",
+            Self::Nixpkg(_) => "nixpkg",
+            Self::Path(_) | Self::Source(_) => "path",
+            Self::FormatFactory { .. } => "format factory",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn try_into_set(self) -> color_eyre::Result<NixSet> {
+        match self {
+            Self::Set(s) => Ok(s),
+            Self::Thunk(t) => t.force()?.try_into_set(),
+            Self::Nixpkg(pkg) => type_error(format!("nixpkg '{}' cannot be treated as set", pkg)),
+            Self::FormatFactory { format_type } => type_error(format!(
+                "'{}' format factory cannot be treated as set",
+                format_type
+            )),
+            Self::Unknown(origin) => type_error(format!(
+                "unknown value '{}' cannot be treated as set",
+                origin
+            )),
+            v => type_error(format!(
+                "value of type {} cannot be treated as set",
+                v.type_name()
+            )),
+        }
+    }
+
+    pub fn try_into_bool(self) -> color_eyre::Result<bool> {
+        match self.force()? {
+            Self::Bool(b) => Ok(b),
+            v => type_error(format!("expected a bool, found {}", v.type_name())),
+        }
+    }
+
+    pub fn try_into_int(self) -> color_eyre::Result<i64> {
+        match self.force()? {
+            Self::Int(i) => Ok(i),
+            v => type_error(format!("expected an integer, found {}", v.type_name())),
+        }
+    }
+
+    pub fn try_into_string(self) -> color_eyre::Result<String> {
+        match self.force()? {
+            Self::Str(s) | Self::DiscardedStr(s, _) => Ok(s),
+            v => type_error(format!("expected a string, found {}", v.type_name())),
+        }
+    }
+
+    // The string with the store paths in it whose context was discarded
+    fn try_into_string_part(self) -> color_eyre::Result<StringPart> {
+        match self.force()? {
+            Self::Str(s) => Ok((s, None)),
+            Self::DiscardedStr(s, discarded) => Ok((s, Some(discarded))),
+            v => type_error(format!("expected a string, found {}", v.type_name())),
+        }
+    }
+
+    pub fn try_into_list(self) -> color_eyre::Result<Rc<Vec<NixObject>>> {
+        match self.force()? {
+            Self::List(l) => Ok(l),
+            v => type_error(format!("expected a list, found {}", v.type_name())),
+        }
+    }
+
+    pub fn apply(self, arg: NixObject) -> color_eyre::Result<NixObject> {
+        match self {
+            Self::Thunk(t) => t.force()?.apply(arg),
+            Self::Lambda(f) => f.apply(arg),
+            Self::PrimOp(op, mut args) => {
+                args.push(arg);
+                if args.len() < op.arity {
+                    return Ok(Self::PrimOp(op, args));
+                }
+                (op.func)(&args)?.force()
+            }
+            Self::FormatFactory { format_type } => Ok(Self::Set(NixSet::Format { format_type })),
+            Self::Unknown(origin) => Ok(Self::Unknown(format!("{} <arg>", origin))),
+            _ => type_error(format!("cannot apply value of type {}", self.type_name())),
+        }
+    }
+}
+
+// A path that would be copied to the store, like builtins.path does. Nothing is copied, so
+// filters are only recorded and never applied
+#[derive(Clone, Debug)]
+pub struct Source {
+    path: String,
+    name: String,
+    filters: Vec<SourceFilter>,
+}
+
+#[derive(Clone, Debug)]
+enum SourceFilter {
+    // A function given to builtins.path or filterSource
+    Custom(NixObject),
+    // lib.cleanSource, which drops version control files, editor backups and build results
+    Clean,
+    // lib.sourceByRegex, which keeps the paths matching any of the regexes
+    Regexes(Vec<String>),
+}
+
+#[derive(Clone)]
+pub struct Thunk {
+    state: Rc<RefCell<ThunkState>>,
+    // Only known when provenance tracking is enabled
+    origin: Option<Provenance>,
+}
+
+enum ThunkState {
+    Pending(Rc<dyn Fn() -> color_eyre::Result<NixObject>>),
+    // Set while the thunk is being forced, so that a value depending on itself is caught
+    Forcing,
+    Done(NixObject),
+}
+
+impl Thunk {
+    fn new(
+        origin: Option<Provenance>,
+        f: impl Fn() -> color_eyre::Result<NixObject> + 'static,
+    ) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(ThunkState::Pending(Rc::new(f)))),
+            origin,
+        }
+    }
+
+    fn force(&self) -> color_eyre::Result<NixObject> {
+        if let ThunkState::Done(v) = &*self.state.borrow() {
+            return Ok(v.clone());
+        }
+        let ThunkState::Pending(f) =
+            std::mem::replace(&mut *self.state.borrow_mut(), ThunkState::Forcing)
+        else {
+            return Err(EvalError::InfiniteRecursion.into());
+        };
+        if let Some(origin) = self.origin {
+            ORIGINS.with(|origins| origins.borrow_mut().push(origin));
+        }
+        let mut res = f().and_then(|v| v.force());
+        if let Some(origin) = self.origin {
+            ORIGINS.with(|origins| origins.borrow_mut().pop());
+            res = res.wrap_err_with(|| format!("{}{}", DEFINED_AT, origin));
+        }
+        // An error is not memoized, so forcing the thunk again retries the evaluation
+        *self.state.borrow_mut() = match &res {
+            Ok(v) => ThunkState::Done(v.clone()),
+            Err(_) => ThunkState::Pending(f),
+        };
+        res
+    }
+}
+
+impl fmt::Debug for Thunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state.try_borrow().as_deref() {
+            Ok(ThunkState::Done(v)) => v.fmt(f),
+            _ => f.write_str("<thunk>"),
+        }
+    }
+}
+
+// A function defined in Nix, closing over the scope it was defined in
+#[derive(Clone)]
+pub struct Lambda {
+    scope: Scope,
+    lambda: rnix::ast::Lambda,
+}
+
+impl Lambda {
+    fn apply(&self, arg: NixObject) -> color_eyre::Result<NixObject> {
+        let mut scope = self.scope.clone();
+        let items = Rc::make_mut(&mut scope.items);
+        match self
+            .lambda
+            .param()
+            .ok_or(EvalError::MalformedAst("lambda without param"))?
+        {
+            Param::IdentParam(param) => {
+                let ident = param
+                    .ident()
+                    .ok_or(EvalError::MalformedAst("param without ident"))?;
+                items.insert(ident.to_string(), arg);
+            }
+            Param::Pattern(pat) => {
+                let set = arg.clone().try_into_set()?;
+                let names = pat
+                    .pat_entries()
+                    .map(|e| {
+                        Ok(e.ident()
+                            .ok_or(EvalError::MalformedAst("pat entry without ident"))?
+                            .to_string())
+                    })
+                    .collect::<color_eyre::Result<Vec<_>>>()?;
+                if let (None, NixSet::Dyn(attrs)) = (pat.ellipsis_token(), &set) {
+                    if let Some(name) = attrs.keys().find(|k| !names.contains(k)) {
+                        return Err(EvalError::UnexpectedArgument(name.clone()).into());
+                    }
+                }
+                // Defaults may refer to the other arguments, so they are evaluated in the
+                //  scope of the body
+                let param_scope = Rc::new(OnceCell::<Scope>::new());
+                for (entry, name) in pat.pat_entries().zip(names) {
+                    let val = match (set.lookup(&name), entry.default()) {
+                        (Some(val), _) => val,
+                        (None, Some(default)) => {
+                            let param_scope = param_scope.clone();
+                            NixObject::lazy(move || {
+                                eval_object(
+                                    param_scope.get().expect("param scope is set"),
+                                    default.clone(),
+                                )
+                            })
+                        }
+                        (None, None) => return Err(EvalError::MissingArgument(name).into()),
+                    };
+                    items.insert(name, val);
+                }
+                if let Some(bind) = pat.pat_bind() {
+                    let ident = bind
+                        .ident()
+                        .ok_or(EvalError::MalformedAst("bind without ident"))?;
+                    items.insert(ident.to_string(), arg);
+                }
+                param_scope
+                    .set(scope.clone())
+                    .expect("param scope is only set once");
+            }
+        }
+        eval_object(
+            &scope,
+            self.lambda
+                .body()
+                .ok_or(EvalError::MalformedAst("lambda without body"))?,
+        )
+    }
+}
+
+impl fmt::Debug for Lambda {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The captured scope is left out, as it may well contain the lambda itself
+        f.debug_tuple("Lambda")
+            .field(&self.lambda.to_string())
+            .finish()
+    }
+}
+
+// A builtin function implemented natively, called once `arity` arguments are applied
+#[derive(Debug)]
+pub struct PrimOp {
+    name: &'static str,
+    arity: usize,
+    func: fn(&[NixObject]) -> color_eyre::Result<NixObject>,
+}
+
+#[derive(Clone, Debug)]
+pub enum NixSet {
+    Dyn(Rc<BTreeMap<String, NixObject>>),
+    Builtins,
+    Lib,
+    LibTypes,
+    LibGenerators,
+    // lib.licenses, which only has the common licenses
+    Licenses,
+    // lib.platforms, whose groups are lists of systems
+    Platforms,
+    // lib.maintainers, from the list given to the evaluation
+    LibMaintainers,
+    Nixpkgs,
+    // pkgs without the overlays from this index on, which is `super` to that overlay
+    PkgsBefore(usize),
+    Config,
+    ConfigVal(Vec<String>),
+    PkgsFormats,
+    Stdenv,
+    // stdenv.hostPlatform and friends, which are all the system being evaluated for
+    Platform,
+    // The result of applying a pkgs.formats factory to its settings
+    Format { format_type: String },
+}
+
+// What a top-level argument of the evaluated file is bound to
+#[derive(Clone, Copy, Debug)]
+pub enum ArgKind {
+    Lib,
+    Pkgs,
+    Config,
+    // The package of the same name, as callPackage would pass from pkgs
+    Pkg,
+    // An empty attribute set
+    Set,
+    Unknown,
+}
+
+impl ArgKind {
+    fn to_object(self, name: &str) -> NixObject {
+        match self {
+            Self::Lib => NixObject::Set(NixSet::Lib),
+            Self::Pkgs => NixObject::Set(NixSet::Nixpkgs),
+            Self::Config => NixObject::Set(NixSet::Config),
+            Self::Pkg => lookup_nixpkg(name),
+            Self::Set => NixObject::from_attrs([]),
+            Self::Unknown => NixObject::Unknown(name.to_owned()),
+        }
+    }
+}
+
+impl FromStr for ArgKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "lib" => Self::Lib,
+            "pkgs" => Self::Pkgs,
+            "config" => Self::Config,
+            "pkg" => Self::Pkg,
+            "set" => Self::Set,
+            "unknown" => Self::Unknown,
+            _ => {
+                return Err(eyre!(
+                "unknown argument kind '{}', expected one of lib, pkgs, config, pkg, set, unknown",
+                s
+            ))
+            }
+        })
+    }
+}
+
+static CALLPACKAGE_ARGS: phf::Map<&'static str, ArgKind> = phf_map! {
+    "lib" => ArgKind::Lib,
+    "pkgs" => ArgKind::Pkgs,
+    "config" => ArgKind::Config,
+    "stdenv" => ArgKind::Pkg,
+};
+
+// https://nixos.org/manual/nix/stable/language/builtins.html
+static BUILTINS: phf::Map<&'static str, PrimOp> = phf_map! {
+    "functionArgs" => PrimOp { name: "functionArgs", arity: 1, func: function_args },
+    "throw" => PrimOp { name: "throw", arity: 1, func: throw },
+    "abort" => PrimOp { name: "abort", arity: 1, func: abort },
+    "seq" => PrimOp { name: "seq", arity: 2, func: seq },
+    "deepSeq" => PrimOp { name: "deepSeq", arity: 2, func: deep_seq },
+    // Not part of Nix: forces its argument completely and returns it
+    "force" => PrimOp { name: "force", arity: 1, func: force },
+    "genericClosure" => PrimOp { name: "genericClosure", arity: 1, func: generic_closure },
+    "catAttrs" => PrimOp { name: "catAttrs", arity: 2, func: cat_attrs },
+    "partition" => PrimOp { name: "partition", arity: 2, func: partition },
+    "concatLists" => PrimOp { name: "concatLists", arity: 1, func: concat_lists },
+    "concatMap" => PrimOp { name: "concatMap", arity: 2, func: concat_map },
+    "any" => PrimOp { name: "any", arity: 2, func: any },
+    "all" => PrimOp { name: "all", arity: 2, func: all },
+    "groupBy" => PrimOp { name: "groupBy", arity: 2, func: group_by },
+    "intersectAttrs" => PrimOp { name: "intersectAttrs", arity: 2, func: intersect_attrs },
+    "map" => PrimOp { name: "map", arity: 2, func: map },
+    "listToAttrs" => PrimOp { name: "listToAttrs", arity: 1, func: list_to_attrs },
+    "split" => PrimOp { name: "split", arity: 2, func: split },
+    "attrNames" => PrimOp { name: "attrNames", arity: 1, func: attr_names },
+    "attrValues" => PrimOp { name: "attrValues", arity: 1, func: attr_values },
+    "mapAttrs" => PrimOp { name: "mapAttrs", arity: 2, func: map_attrs },
+    "toJSON" => PrimOp { name: "toJSON", arity: 1, func: to_json },
+    "fromTOML" => PrimOp { name: "fromTOML", arity: 1, func: from_toml },
+    "toFile" => PrimOp { name: "toFile", arity: 2, func: to_file },
+    "hashString" => PrimOp { name: "hashString", arity: 2, func: hash_string },
+    "hashFile" => PrimOp { name: "hashFile", arity: 2, func: hash_file },
+    "compareVersions" => PrimOp { name: "compareVersions", arity: 2, func: compare_versions },
+    "splitVersion" => PrimOp { name: "splitVersion", arity: 1, func: split_version },
+    "parseDrvName" => PrimOp { name: "parseDrvName", arity: 1, func: parse_drv_name },
+    "isPath" => PrimOp { name: "isPath", arity: 1, func: is_path },
+    "getEnv" => PrimOp { name: "getEnv", arity: 1, func: get_env },
+    "import" => PrimOp { name: "import", arity: 1, func: import },
+    "bitAnd" => PrimOp { name: "bitAnd", arity: 2, func: bit_and },
+    "bitOr" => PrimOp { name: "bitOr", arity: 2, func: bit_or },
+    "bitXor" => PrimOp { name: "bitXor", arity: 2, func: bit_xor },
+    "ceil" => PrimOp { name: "ceil", arity: 1, func: ceil },
+    "floor" => PrimOp { name: "floor", arity: 1, func: floor },
+    "foldl'" => PrimOp { name: "foldl'", arity: 3, func: foldl },
+    "toString" => PrimOp { name: "toString", arity: 1, func: to_string },
+    "path" => PrimOp { name: "path", arity: 1, func: path },
+    "addErrorContext" => PrimOp { name: "addErrorContext", arity: 2, func: add_error_context },
+    "baseNameOf" => PrimOp { name: "baseNameOf", arity: 1, func: base_name_of },
+    "dirOf" => PrimOp { name: "dirOf", arity: 1, func: dir_of },
+    "placeholder" => PrimOp { name: "placeholder", arity: 1, func: placeholder },
+    "hasContext" => PrimOp { name: "hasContext", arity: 1, func: has_context },
+    "unsafeDiscardStringContext" => PrimOp {
+        name: "unsafeDiscardStringContext",
+        arity: 1,
+        func: discard_string_context,
+    },
+    "unsafeDiscardOutputDependency" => PrimOp {
+        name: "unsafeDiscardOutputDependency",
+        arity: 1,
+        func: discard_output_dependency,
+    },
+    "filterSource" => PrimOp { name: "filterSource", arity: 2, func: filter_source },
+    "zipAttrsWith" => PrimOp { name: "zipAttrsWith", arity: 2, func: zip_attrs_with },
+};
+
+// https://github.com/NixOS/nixpkgs/blob/master/lib/trivial.nix
+// https://github.com/NixOS/nixpkgs/blob/master/lib/options.nix
+// https://github.com/NixOS/nixpkgs/blob/master/lib/modules.nix
+static LIB: phf::Map<&'static str, PrimOp> = phf_map! {
+    "functionArgs" => PrimOp { name: "functionArgs", arity: 1, func: lib_function_args },
+    "pipe" => PrimOp { name: "pipe", arity: 2, func: pipe },
+    "flip" => PrimOp { name: "flip", arity: 3, func: flip },
+    "const" => PrimOp { name: "const", arity: 2, func: const_ },
+    "catAttrs" => PrimOp { name: "catAttrs", arity: 2, func: cat_attrs },
+    "partition" => PrimOp { name: "partition", arity: 2, func: partition },
+    "attrNames" => PrimOp { name: "attrNames", arity: 1, func: attr_names },
+    "attrValues" => PrimOp { name: "attrValues", arity: 1, func: attr_values },
+    "mapAttrs" => PrimOp { name: "mapAttrs", arity: 2, func: map_attrs },
+    "mapAttrs'" => PrimOp { name: "mapAttrs'", arity: 2, func: map_attrs_renamed },
+    "mapAttrsRecursive" => PrimOp {
+        name: "mapAttrsRecursive",
+        arity: 2,
+        func: map_attrs_recursive,
+    },
+    "mapAttrsRecursiveCond" => PrimOp {
+        name: "mapAttrsRecursiveCond",
+        arity: 3,
+        func: map_attrs_recursive_cond,
+    },
+    "genAttrs" => PrimOp { name: "genAttrs", arity: 2, func: gen_attrs },
+    "attrByPath" => PrimOp { name: "attrByPath", arity: 3, func: attr_by_path },
+    "setAttrByPath" => PrimOp { name: "setAttrByPath", arity: 2, func: set_attr_by_path },
+    "hasAttrByPath" => PrimOp { name: "hasAttrByPath", arity: 2, func: has_attr_by_path },
+    "count" => PrimOp { name: "count", arity: 2, func: count },
+    "imap0" => PrimOp { name: "imap0", arity: 2, func: imap0 },
+    "imap1" => PrimOp { name: "imap1", arity: 2, func: imap1 },
+    "escapeNixString" => PrimOp { name: "escapeNixString", arity: 1, func: escape_nix_string },
+    "cleanSource" => PrimOp { name: "cleanSource", arity: 1, func: clean_source },
+    "sourceByRegex" => PrimOp { name: "sourceByRegex", arity: 2, func: source_by_regex },
+    "zipAttrsWith" => PrimOp { name: "zipAttrsWith", arity: 2, func: zip_attrs_with },
+    "zipAttrs" => PrimOp { name: "zipAttrs", arity: 1, func: zip_attrs },
+    "concatLists" => PrimOp { name: "concatLists", arity: 1, func: concat_lists },
+    "concatMap" => PrimOp { name: "concatMap", arity: 2, func: concat_map },
+    "cartesianProductOfSets" => PrimOp {
+        name: "cartesianProductOfSets",
+        arity: 1,
+        func: cartesian_product,
+    },
+    "cartesianProduct" => PrimOp { name: "cartesianProduct", arity: 1, func: cartesian_product },
+    "crossLists" => PrimOp { name: "crossLists", arity: 2, func: cross_lists },
+    "zipListsWith" => PrimOp { name: "zipListsWith", arity: 3, func: zip_lists_with },
+    "zipLists" => PrimOp { name: "zipLists", arity: 2, func: zip_lists },
+    "any" => PrimOp { name: "any", arity: 2, func: any },
+    "all" => PrimOp { name: "all", arity: 2, func: all },
+    "groupBy" => PrimOp { name: "groupBy", arity: 2, func: group_by },
+    "intersectAttrs" => PrimOp { name: "intersectAttrs", arity: 2, func: intersect_attrs },
+    "flatten" => PrimOp { name: "flatten", arity: 1, func: flatten },
+    "range" => PrimOp { name: "range", arity: 2, func: range },
+    "last" => PrimOp { name: "last", arity: 1, func: last },
+    "init" => PrimOp { name: "init", arity: 1, func: init },
+    "foldl" => PrimOp { name: "foldl", arity: 3, func: foldl },
+    "foldl'" => PrimOp { name: "foldl'", arity: 3, func: foldl },
+    "foldr" => PrimOp { name: "foldr", arity: 3, func: foldr },
+    "fold" => PrimOp { name: "fold", arity: 3, func: foldr },
+    "optional" => PrimOp { name: "optional", arity: 2, func: optional },
+    "optionals" => PrimOp { name: "optionals", arity: 2, func: optionals },
+    "unique" => PrimOp { name: "unique", arity: 1, func: unique },
+    "subtractLists" => PrimOp { name: "subtractLists", arity: 2, func: subtract_lists },
+    "nameValuePair" => PrimOp { name: "nameValuePair", arity: 2, func: name_value_pair },
+    "listToAttrs" => PrimOp { name: "listToAttrs", arity: 1, func: list_to_attrs },
+    "splitString" => PrimOp { name: "splitString", arity: 2, func: split_string },
+    "hasPrefix" => PrimOp { name: "hasPrefix", arity: 2, func: has_prefix },
+    "hasSuffix" => PrimOp { name: "hasSuffix", arity: 2, func: has_suffix },
+    "removePrefix" => PrimOp { name: "removePrefix", arity: 2, func: remove_prefix },
+    "removeSuffix" => PrimOp { name: "removeSuffix", arity: 2, func: remove_suffix },
+    "toUpper" => PrimOp { name: "toUpper", arity: 1, func: to_upper },
+    "toLower" => PrimOp { name: "toLower", arity: 1, func: to_lower },
+    "stringToCharacters" => PrimOp {
+        name: "stringToCharacters",
+        arity: 1,
+        func: string_to_characters,
+    },
+    "charToInt" => PrimOp { name: "charToInt", arity: 1, func: char_to_int },
+    "toInt" => PrimOp { name: "toInt", arity: 1, func: to_int },
+    "toIntBase10" => PrimOp { name: "toIntBase10", arity: 1, func: to_int_base10 },
+    "escapeShellArg" => PrimOp { name: "escapeShellArg", arity: 1, func: escape_shell_arg },
+    "escapeShellArgs" => PrimOp { name: "escapeShellArgs", arity: 1, func: escape_shell_args },
+    "mkOption" => PrimOp { name: "mkOption", arity: 1, func: mk_option },
+    "mkEnableOption" => PrimOp { name: "mkEnableOption", arity: 1, func: mk_enable_option },
+    "literalExpression" => PrimOp {
+        name: "literalExpression",
+        arity: 1,
+        func: literal_expression,
+    },
+    "mkIf" => PrimOp { name: "mkIf", arity: 2, func: mk_if },
+    "mkMerge" => PrimOp { name: "mkMerge", arity: 1, func: mk_merge },
+    "mkOverride" => PrimOp { name: "mkOverride", arity: 2, func: mk_override },
+    "mkDefault" => PrimOp { name: "mkDefault", arity: 1, func: mk_default },
+    "mkForce" => PrimOp { name: "mkForce", arity: 1, func: mk_force },
+    "recursiveUpdate" => PrimOp { name: "recursiveUpdate", arity: 2, func: recursive_update },
+    "compareVersions" => PrimOp { name: "compareVersions", arity: 2, func: compare_versions },
+    "splitVersion" => PrimOp { name: "splitVersion", arity: 1, func: split_version },
+    "versionOlder" => PrimOp { name: "versionOlder", arity: 2, func: version_older },
+    "versionAtLeast" => PrimOp { name: "versionAtLeast", arity: 2, func: version_at_least },
+    "getName" => PrimOp { name: "getName", arity: 1, func: get_name },
+    "getVersion" => PrimOp { name: "getVersion", arity: 1, func: get_version },
+    "fix" => PrimOp { name: "fix", arity: 1, func: fix },
+    "extends" => PrimOp { name: "extends", arity: 3, func: extends },
+    "makeExtensible" => PrimOp { name: "makeExtensible", arity: 1, func: make_extensible },
+    "makeOverridable" => PrimOp { name: "makeOverridable", arity: 2, func: make_overridable },
+};
+
+// The `extend` attribute of sets made by lib.makeExtensible, applied to the function that
+// made the set up front
+static EXTEND: PrimOp = PrimOp {
+    name: "extend",
+    arity: 2,
+    func: extend,
+};
+
+// The function lib.makeExtensible takes the fixed point of, which adds `extend` to the set
+static EXTENSIBLE: PrimOp = PrimOp {
+    name: "makeExtensible",
+    arity: 2,
+    func: extensible,
+};
+
+// The `override` attribute of results of lib.makeOverridable, applied to its function and
+// the arguments it was called with up front
+static OVERRIDE: PrimOp = PrimOp {
+    name: "override",
+    arity: 3,
+    func: override_,
+};
+
+// Applied to an attribute name, its values and a combination, adds each of the values to
+// the combination
+static ADD_COMBINATION_VALUE: PrimOp = PrimOp {
+    name: "cartesianProductOfSets",
+    arity: 3,
+    func: add_combination_value,
+};
+
+// `generate` of a pkgs.formats format, which is applied to the format type up front
+static GENERATE: PrimOp = PrimOp {
+    name: "generate",
+    arity: 3,
+    func: generate,
+};
+
+static MK_DERIVATION: PrimOp = PrimOp {
+    name: "mkDerivation",
+    arity: 1,
+    func: mk_derivation,
+};
+
+// https://github.com/NixOS/nixpkgs/blob/master/pkgs/build-support/trivial-builders/default.nix
+static TRIVIAL_BUILDERS: phf::Map<&'static str, PrimOp> = phf_map! {
+    "writeTextFile" => PrimOp { name: "writeTextFile", arity: 1, func: write_text_file },
+    "writeText" => PrimOp { name: "writeText", arity: 2, func: write_text },
+    "writeShellScript" => PrimOp { name: "writeShellScript", arity: 2, func: write_shell_script },
+    "writeShellScriptBin" => PrimOp {
+        name: "writeShellScriptBin",
+        arity: 2,
+        func: write_shell_script_bin,
+    },
+    "runCommand" => PrimOp { name: "runCommand", arity: 3, func: run_command },
+    "symlinkJoin" => PrimOp { name: "symlinkJoin", arity: 1, func: symlink_join },
+};
+
+// https://github.com/NixOS/nixpkgs/blob/master/lib/generators.nix
+static GENERATORS: phf::Map<&'static str, PrimOp> = phf_map! {
+    "mkValueStringDefault" => PrimOp {
+        name: "mkValueStringDefault",
+        arity: 2,
+        func: mk_value_string_default,
+    },
+    "mkKeyValueDefault" => PrimOp {
+        name: "mkKeyValueDefault",
+        arity: 4,
+        func: mk_key_value_default,
+    },
+    "toKeyValue" => PrimOp { name: "toKeyValue", arity: 2, func: to_key_value },
+    "toINI" => PrimOp { name: "toINI", arity: 2, func: to_ini },
+    "toINIWithGlobalSection" => PrimOp {
+        name: "toINIWithGlobalSection",
+        arity: 2,
+        func: to_ini_with_global_section,
+    },
+    "toGitINI" => PrimOp { name: "toGitINI", arity: 1, func: to_git_ini },
+    "toJSON" => PrimOp { name: "toJSON", arity: 2, func: generators_to_json },
+    "toYAML" => PrimOp { name: "toYAML", arity: 2, func: generators_to_json },
+};
+
+// https://github.com/NixOS/nixpkgs/blob/master/lib/types.nix
+static TYPE_CONSTRUCTORS: phf::Map<&'static str, PrimOp> = phf_map! {
+    "listOf" => PrimOp { name: "listOf", arity: 1, func: list_of },
+    "attrsOf" => PrimOp { name: "attrsOf", arity: 1, func: attrs_of },
+    "nullOr" => PrimOp { name: "nullOr", arity: 1, func: null_or },
+};
+
+fn lookup_nixpkg(name: &str) -> NixObject {
+    match name {
+        "formats" => NixObject::Set(NixSet::PkgsFormats),
+        "stdenv" => NixObject::Set(NixSet::Stdenv),
+        "lib" => NixObject::Set(NixSet::Lib),
+        _ => match TRIVIAL_BUILDERS.get(name) {
+            Some(op) => NixObject::PrimOp(op, Vec::new()),
+            None => NixObject::Nixpkg(name.to_owned()),
+        },
+    }
+}
+
+fn lookup_lib(name: &str) -> Option<NixObject> {
+    match name {
+        "types" => return Some(NixObject::Set(NixSet::LibTypes)),
+        "generators" => return Some(NixObject::Set(NixSet::LibGenerators)),
+        "platforms" => return Some(NixObject::Set(NixSet::Platforms)),
+        "systems" => {
+            return Some(NixObject::from_attrs([(
+                "doubles",
+                NixObject::Set(NixSet::Platforms),
+            )]))
+        }
+        "licenses" => return Some(NixObject::Set(NixSet::Licenses)),
+        "maintainers" => return Some(NixObject::Set(NixSet::LibMaintainers)),
+        "fixedPoints" => {
+            return Some(NixObject::from_attrs(
+                ["fix", "extends", "makeExtensible"]
+                    .map(|name| (name, NixObject::PrimOp(&LIB[name], Vec::new()))),
+            ))
+        }
+        _ => {}
+    }
+    LIB.get(name).map(|op| NixObject::PrimOp(op, Vec::new()))
+}
+
+fn lookup_type(name: &str) -> NixObject {
+    match TYPE_CONSTRUCTORS.get(name) {
+        Some(op) => NixObject::PrimOp(op, Vec::new()),
+        None => option_type(name, []),
+    }
+}
+
+// Values that are in scope everywhere
+fn lookup_global(name: &str) -> Option<NixObject> {
+    match name {
+        "true" => Some(NixObject::Bool(true)),
+        "false" => Some(NixObject::Bool(false)),
+        "null" => Some(NixObject::Null),
+        "builtins" => Some(NixObject::Set(NixSet::Builtins)),
+        "throw" | "abort" | "map" | "toString" | "baseNameOf" | "dirOf" | "import" => BUILTINS
+            .get(name)
+            .map(|op| NixObject::PrimOp(op, Vec::new())),
+        _ => None,
+    }
+}
+
+impl NixSet {
+    pub fn lookup(&self, k: &str) -> Option<NixObject> {
+        match self {
+            Self::Dyn(s) => s.get(k).cloned(),
+            Self::Builtins => lookup_builtin(k),
+            Self::Lib => lookup_lib(k),
+            Self::LibTypes => Some(lookup_type(k)),
+            Self::LibGenerators => GENERATORS
+                .get(k)
+                .map(|op| NixObject::PrimOp(op, Vec::new())),
+            Self::Licenses => license(k),
+            Self::Platforms => platform_group(k)
+                .or_else(|| lenient().then(|| NixObject::Unknown(format!("lib.platforms.{}", k)))),
+            Self::LibMaintainers => maintainer(k),
+            Self::Nixpkgs => lookup_pkgs_attr(k, usize::MAX),
+            Self::PkgsBefore(layers) => lookup_pkgs_attr(k, *layers),
+            Self::Config => Some(lookup_config(vec![k.to_owned()])),
+            Self::ConfigVal(path) => Some(lookup_config(
+                path.iter()
+                    .cloned()
+                    .chain(std::iter::once(k.to_owned()))
+                    .collect(),
+            )),
+            // https://github.com/NixOS/nixpkgs/blob/master/pkgs/pkgs-lib/formats.nix
+            Self::PkgsFormats => Some(NixObject::FormatFactory {
+                format_type: k.to_owned(),
+            }),
+            // https://github.com/NixOS/nixpkgs/blob/master/lib/systems/inspect.nix
+            Self::Stdenv => match k {
+                "mkDerivation" => Some(NixObject::PrimOp(&MK_DERIVATION, Vec::new())),
+                "hostPlatform" | "buildPlatform" | "targetPlatform" => {
+                    Some(NixObject::Set(NixSet::Platform))
+                }
+                "system" => Some(NixObject::Str(current_system())),
+                _ => platform_predicate(k),
+            },
+            Self::Platform => match k {
+                "system" => Some(NixObject::Str(current_system())),
+                _ => platform_predicate(k),
+            },
+            Self::Format { format_type } => match k {
+                "type" => Some(option_type(&format!("{} value", format_type), [])),
+                "generate" => Some(NixObject::PrimOp(
+                    &GENERATE,
+                    vec![NixObject::Str(format_type.clone())],
+                )),
+                _ => None,
+            },
+        }
+    }
+}
+
+thread_local! {
+    // The Nix system double being evaluated for, like x86_64-linux
+    static SYSTEM: RefCell<String> = RefCell::new(host_system());
+}
+
+/// The system of the machine running the evaluation, like x86_64-linux
+pub fn host_system() -> String {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    format!("{}-{}", std::env::consts::ARCH, os)
+}
+
+fn current_system() -> String {
+    SYSTEM.with(|system| system.borrow().clone())
+}
+
+/// The Nix version reported by builtins.nixVersion unless another one is given
+pub const DEFAULT_NIX_VERSION: &str = "2.24.0";
+
+thread_local! {
+    // What builtins.storeDir and builtins.nixVersion evaluate to
+    static STORE_DIR: RefCell<String> = RefCell::new("/nix/store".to_owned());
+    static NIX_VERSION: RefCell<String> = RefCell::new(DEFAULT_NIX_VERSION.to_owned());
+}
+
+fn lookup_builtin(name: &str) -> Option<NixObject> {
+    match name {
+        "currentSystem" => Some(NixObject::Str(current_system())),
+        "storeDir" => Some(NixObject::Str(STORE_DIR.with(|dir| dir.borrow().clone()))),
+        "nixVersion" => Some(NixObject::Str(
+            NIX_VERSION.with(|version| version.borrow().clone()),
+        )),
+        // The version of the language, which has been 6 since Nix 2.3
+        "langVersion" => Some(NixObject::Int(6)),
+        "nixPath" => Some(nix_path()),
+        _ => BUILTINS
+            .get(name)
+            .map(|op| NixObject::PrimOp(op, Vec::new())),
+    }
+}
+
+// The isLinux, isDarwin, ... attributes of stdenv and platforms
+fn platform_predicate(name: &str) -> Option<NixObject> {
+    let system = current_system();
+    let (cpu, kernel) = system.split_once('-').unwrap_or((&system, ""));
+    let res = match name {
+        "isLinux" => kernel == "linux",
+        "isDarwin" => kernel == "darwin",
+        "isFreeBSD" => kernel == "freebsd",
+        "isx86_64" => cpu == "x86_64",
+        "isi686" => cpu == "i686",
+        "isAarch64" => cpu == "aarch64",
+        "isx86" => cpu == "x86_64" || cpu == "i686",
+        "isAarch" => cpu.starts_with("aarch") || cpu.starts_with("arm"),
+        _ => return None,
+    };
+    Some(NixObject::Bool(res))
+}
+
+// https://github.com/NixOS/nixpkgs/blob/master/lib/licenses.nix
+// The SPDX id, full name and whether the license is free, for the common ones
+static LICENSES: phf::Map<&'static str, (Option<&'static str>, &'static str, bool)> = phf_map! {
+    "agpl3Only" => (Some("AGPL-3.0-only"), "GNU Affero General Public License v3.0 only", true),
+    "agpl3Plus" => (Some("AGPL-3.0-or-later"), "GNU Affero General Public License v3.0 or later", true),
+    "asl20" => (Some("Apache-2.0"), "Apache License 2.0", true),
+    "bsd2" => (Some("BSD-2-Clause"), "BSD 2-clause \"Simplified\" License", true),
+    "bsd3" => (Some("BSD-3-Clause"), "BSD 3-clause \"New\" or \"Revised\" License", true),
+    "bsl11" => (Some("BUSL-1.1"), "Business Source License 1.1", false),
+    "cc0" => (Some("CC0-1.0"), "Creative Commons Zero v1.0 Universal", true),
+    "epl20" => (Some("EPL-2.0"), "Eclipse Public License 2.0", true),
+    "gpl2Only" => (Some("GPL-2.0-only"), "GNU General Public License v2.0 only", true),
+    "gpl2Plus" => (Some("GPL-2.0-or-later"), "GNU General Public License v2.0 or later", true),
+    "gpl3Only" => (Some("GPL-3.0-only"), "GNU General Public License v3.0 only", true),
+    "gpl3Plus" => (Some("GPL-3.0-or-later"), "GNU General Public License v3.0 or later", true),
+    "isc" => (Some("ISC"), "ISC License", true),
+    "lgpl21Only" => (Some("LGPL-2.1-only"), "GNU Lesser General Public License v2.1 only", true),
+    "lgpl21Plus" => (Some("LGPL-2.1-or-later"), "GNU Lesser General Public License v2.1 or later", true),
+    "lgpl3Only" => (Some("LGPL-3.0-only"), "GNU Lesser General Public License v3.0 only", true),
+    "lgpl3Plus" => (Some("LGPL-3.0-or-later"), "GNU Lesser General Public License v3.0 or later", true),
+    "mit" => (Some("MIT"), "MIT License", true),
+    "mpl20" => (Some("MPL-2.0"), "Mozilla Public License 2.0", true),
+    "ofl" => (Some("OFL-1.1"), "SIL Open Font License 1.1", true),
+    "openssl" => (Some("OpenSSL"), "OpenSSL License", true),
+    "psfl" => (Some("Python-2.0"), "Python Software Foundation License version 2", true),
+    "publicDomain" => (None, "Public Domain", true),
+    "unfree" => (None, "Unfree", false),
+    "unlicense" => (Some("Unlicense"), "The Unlicense", true),
+    "zlib" => (Some("Zlib"), "zlib License", true),
+};
+
+// Licenses that aren't known are unknown in lenient mode
+fn license(name: &str) -> Option<NixObject> {
+    let Some((spdx_id, full_name, free)) = LICENSES.get(name) else {
+        return lenient().then(|| NixObject::Unknown(format!("lib.licenses.{}", name)));
+    };
+    let mut attrs = vec![
+        ("shortName", NixObject::Str(name.to_owned())),
+        ("fullName", NixObject::Str((*full_name).to_owned())),
+        ("free", NixObject::Bool(*free)),
+    ];
+    if let Some(spdx_id) = spdx_id {
+        attrs.push(("spdxId", NixObject::Str((*spdx_id).to_owned())));
+    }
+    Some(NixObject::from_attrs(attrs))
+}
+
+// A subset of lib.systems.doubles, for the usual values of meta.platforms
+const SYSTEMS: [&str; 14] = [
+    "aarch64-linux",
+    "armv6l-linux",
+    "armv7l-linux",
+    "i686-linux",
+    "powerpc64le-linux",
+    "riscv64-linux",
+    "x86_64-linux",
+    "aarch64-darwin",
+    "x86_64-darwin",
+    "aarch64-freebsd",
+    "i686-freebsd",
+    "x86_64-freebsd",
+    "i686-windows",
+    "x86_64-windows",
+];
+
+// https://github.com/NixOS/nixpkgs/blob/master/lib/systems/doubles.nix
+// The groups of lib.platforms, which are the same as those of lib.systems.doubles
+fn platform_group(name: &str) -> Option<NixObject> {
+    let pred: fn(&str) -> bool = match name {
+        "all" => |_| true,
+        "unix" => |s| !s.ends_with("-windows"),
+        "linux" => |s| s.ends_with("-linux"),
+        "darwin" => |s| s.ends_with("-darwin"),
+        "freebsd" => |s| s.ends_with("-freebsd"),
+        "windows" => |s| s.ends_with("-windows"),
+        "x86" => |s| s.starts_with("x86_64-") || s.starts_with("i686-"),
+        "x86_64" => |s| s.starts_with("x86_64-"),
+        "i686" => |s| s.starts_with("i686-"),
+        "aarch64" => |s| s.starts_with("aarch64-"),
+        "arm" => |s| s.starts_with("arm"),
+        "power" => |s| s.starts_with("powerpc"),
+        "riscv" => |s| s.starts_with("riscv"),
+        _ => return None,
+    };
+    Some(NixObject::List(Rc::new(
+        SYSTEMS
+            .iter()
+            .filter(|s| pred(s))
+            .map(|s| NixObject::Str((*s).to_owned()))
+            .collect(),
+    )))
+}
+
+/// A position in the evaluated source, counted from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+// The context added to errors in values whose definition is known
+const DEFINED_AT: &str = "while evaluating the value defined at ";
+
+/// Where the innermost value that was being evaluated when `err` happened is defined,
+/// which is only known when provenance is tracked
+pub fn error_origin(err: &eyre::Report) -> Option<Provenance> {
+    err.chain()
+        .filter_map(|err| {
+            let err = err.to_string();
+            let (line, column) = err.strip_prefix(DEFINED_AT)?.split_once(", ")?;
+            Some(Provenance {
+                line: line.strip_prefix("line ")?.parse().ok()?,
+                column: column.strip_prefix("column ")?.parse().ok()?,
+            })
+        })
+        .last()
+}
+
+thread_local! {
+    // The syntax tree of the source and the byte offsets at which its lines start, only
+    // set when provenance is tracked
+    static LINE_STARTS: RefCell<Option<(rnix::SyntaxNode, Rc<Vec<usize>>)>> =
+        const { RefCell::new(None) };
+}
+
+thread_local! {
+    // Where the values currently being forced are defined, innermost last
+    static ORIGINS: RefCell<Vec<Provenance>> = const { RefCell::new(Vec::new()) };
+}
+
+fn current_origin() -> Option<Provenance> {
+    ORIGINS.with(|origins| origins.borrow().last().copied())
+}
+
+fn provenance_of(expr: &Expr) -> Option<Provenance> {
+    LINE_STARTS.with(|starts| {
+        let starts = starts.borrow();
+        let (root, starts) = starts.as_ref()?;
+        // Overlays come from other files, which positions can't refer to
+        if expr.syntax().ancestors().last().as_ref() != Some(root) {
+            return None;
+        }
+        let offset = usize::from(expr.syntax().text_range().start());
+        let line = starts.partition_point(|&start| start <= offset);
+        Some(Provenance {
+            line,
+            column: offset - starts[line - 1] + 1,
+        })
+    })
+}
+
+// Configuration that lookups on the `config` argument are resolved against
+#[derive(Default)]
+struct ConfigState {
+    // Option declarations found in the module's `options`, keyed by attribute path
+    declarations: HashMap<Vec<String>, NixSet>,
+    // Values supplied by the user, which take precedence over option defaults
+    values: Option<NixObject>,
+    // Undeclared paths that have already been warned about, along with everything below them
+    warned: HashSet<Vec<String>>,
+    // Every path looked up, with where it was first looked up from
+    accessed: BTreeMap<Vec<String>, Option<Provenance>>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<ConfigState> = RefCell::default();
+}
+
+fn config_value(config: &ConfigState, path: &[String]) -> Option<NixObject> {
+    config.values.as_ref().and_then(|values| {
+        path.iter().try_fold(values.clone(), |set, k| match set {
+            NixObject::Set(set) => set.lookup(k),
+            _ => None,
+        })
+    })
+}
+
+// The attributes of a set, with symbolic config sets expanded into the options declared
+// and the values given below them. None if they can't be known
+fn materialize(set: NixSet) -> color_eyre::Result<Option<Rc<BTreeMap<String, NixObject>>>> {
+    let path = match set {
+        NixSet::Dyn(attrs) => return Ok(Some(attrs)),
+        NixSet::Config => Vec::new(),
+        NixSet::ConfigVal(path) => path,
+        _ => return Ok(None),
+    };
+    let names = CONFIG.with(|config| {
+        let config = config.borrow();
+        let mut names = config
+            .declarations
+            .keys()
+            .filter(|p| p.len() > path.len() && p.starts_with(&path))
+            .map(|p| p[path.len()].clone())
+            .collect::<BTreeSet<_>>();
+        if let Some(value) = config_value(&config, &path) {
+            if let NixObject::Set(NixSet::Dyn(attrs)) = value.force()? {
+                names.extend(attrs.keys().cloned());
+            }
+        }
+        Ok::<_, color_eyre::Report>(names)
+    })?;
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let attrs = names
+        .into_iter()
+        .map(|k| {
+            let val = lookup_config(path.iter().cloned().chain([k.clone()]).collect());
+            (k, val)
+        })
+        .collect();
+    Ok(Some(Rc::new(attrs)))
+}
+
+fn lookup_config(path: Vec<String>) -> NixObject {
+    CONFIG.with(|config| {
+        let mut config = config.borrow_mut();
+        config
+            .accessed
+            .entry(path.clone())
+            .or_insert_with(current_origin);
+        let value = config_value(&config, &path);
+        if let Some(option) = config.declarations.get(&path) {
+            return value
+                .or_else(|| option.lookup("default"))
+                .unwrap_or(NixObject::Set(NixSet::ConfigVal(path)));
+        }
+        // Keep intermediate paths symbolic so the accesses below them are still checked
+        if config.declarations.keys().any(|p| p.starts_with(&path)) {
+            return NixObject::Set(NixSet::ConfigVal(path));
+        }
+        if !config.declarations.is_empty() && !config.warned.iter().any(|w| path.starts_with(w)) {
+            warn(&format!("access to undeclared option '{}'", path.join(".")));
+            config.warned.insert(path.clone());
+        }
+        value.unwrap_or(NixObject::Set(NixSet::ConfigVal(path)))
+    })
+}
+
+// Package names that lookups on `pkgs` are checked against
+#[derive(Default)]
+struct PkgsState {
+    // Every name is assumed to be a package when no list is given
+    known: Option<HashSet<String>>,
+    // Unknown names only produce a warning instead of being missing
+    lenient: bool,
+    warned: HashSet<String>,
+    // The attributes each overlay adds, in the order they are applied
+    overlays: Vec<Rc<BTreeMap<String, NixObject>>>,
+    // Names of the overlay packages by the sets they evaluated to, so the dependency pass
+    // can report them like other packages
+    overlay_packages: HashMap<*const BTreeMap<String, NixObject>, String>,
+}
+
+thread_local! {
+    static PKGS: RefCell<PkgsState> = RefCell::default();
+}
+
+// The maintainers that lookups on lib.maintainers are checked against, like packages
+#[derive(Default)]
+struct MaintainersState {
+    // Every handle is assumed to be a maintainer when no list is given
+    known: Option<Rc<BTreeMap<String, NixObject>>>,
+    warned: HashSet<String>,
+}
+
+thread_local! {
+    static MAINTAINERS: RefCell<MaintainersState> = RefCell::default();
+}
+
+// Maintainers that aren't in the list are only known by their handle
+fn maintainer(handle: &str) -> Option<NixObject> {
+    let stand_in = || NixObject::from_attrs([("github", NixObject::Str(handle.to_owned()))]);
+    MAINTAINERS.with(|maintainers| {
+        let mut maintainers = maintainers.borrow_mut();
+        let Some(known) = &maintainers.known else {
+            return Some(stand_in());
+        };
+        if let Some(maintainer) = known.get(handle) {
+            return Some(maintainer.clone());
+        }
+        if !lenient() {
+            return None;
+        }
+        if maintainers.warned.insert(handle.to_owned()) {
+            warn(&format!("unknown maintainer 'lib.maintainers.{}'", handle));
+        }
+        Some(stand_in())
+    })
+}
+
+// Only the first `layers` overlays are taken into account
+fn lookup_pkgs_attr(name: &str, layers: usize) -> Option<NixObject> {
+    let from_overlay = PKGS.with(|pkgs| {
+        let pkgs = pkgs.borrow();
+        let mut overlays = pkgs.overlays.iter().take(layers).rev();
+        overlays.find_map(|attrs| attrs.get(name).cloned())
+    });
+    if let Some(val) = from_overlay {
+        let name = name.to_owned();
+        return Some(NixObject::lazy(move || {
+            let val = val.force()?;
+            if let NixObject::Set(NixSet::Dyn(attrs)) = &val {
+                PKGS.with(|pkgs| {
+                    let mut pkgs = pkgs.borrow_mut();
+                    pkgs.overlay_packages
+                        .insert(Rc::as_ptr(attrs), name.clone());
+                });
+            }
+            Ok(val)
+        }));
+    }
+    PKGS.with(|pkgs| {
+        let mut pkgs = pkgs.borrow_mut();
+        match &pkgs.known {
+            Some(known)
+                if !matches!(name, "formats" | "lib")
+                    && !TRIVIAL_BUILDERS.contains_key(name)
+                    && !known.contains(name) =>
+            {
+                if !pkgs.lenient {
+                    return None;
+                }
+                if pkgs.warned.insert(name.to_owned()) {
+                    warn(&format!("access to unknown package 'pkgs.{}'", name));
+                }
+            }
+            _ => {}
+        }
+        Some(lookup_nixpkg(name))
+    })
+}
+
+thread_local! {
+    // Warnings printed since the evaluation started, with where they were caused
+    static WARNINGS: RefCell<Vec<(String, Option<Provenance>)>> = const { RefCell::new(Vec::new()) };
+}
+
+thread_local! {
+    // Errors in parts of the value that lenient mode printed a stand-in for
+    static ERRORS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An error that lenient mode recovered from while printing a value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// What kind of error it is, like `"type error"`
+    pub kind: &'static str,
+    pub message: String,
+    /// Where the value that failed is defined, only known when provenance is tracked
+    pub origin: Option<Provenance>,
+    /// The error itself, unless it didn't come from the evaluation
+    pub error: Option<EvalError>,
+}
+
+fn error_kind(err: &eyre::Report) -> &'static str {
+    match err.root_cause().downcast_ref::<EvalError>() {
+        Some(EvalError::TypeError(_)) => "type error",
+        Some(EvalError::NotInScope(_)) => "not in scope",
+        Some(EvalError::UnknownBuiltin(_)) => "unknown builtin",
+        Some(EvalError::MissingAttribute(_)) => "missing attribute",
+        Some(EvalError::Throw(_) | EvalError::Abort(_) | EvalError::AssertFailure(_)) => {
+            "thrown error"
+        }
+        _ => "other error",
+    }
+}
+
+/// The errors printed as `«error»` by [`print_value`] in lenient mode since the last call
+/// to [`eval_str`], in the order they were found
+pub fn errors() -> Vec<Diagnostic> {
+    ERRORS.with(|errors| errors.borrow().clone())
+}
+
+// In lenient mode, values that fail to evaluate are printed as `«error»` and the error is
+// kept, so that one bad attribute doesn't hide the others
+fn print_or_recover(val: &NixObject) -> color_eyre::Result<String> {
+    match print_value(val) {
+        Err(err) if lenient() => {
+            let diagnostic = Diagnostic {
+                kind: error_kind(&err),
+                message: err.root_cause().to_string(),
+                origin: val.provenance(),
+                error: err.root_cause().downcast_ref::<EvalError>().cloned(),
+            };
+            ERRORS.with(|errors| errors.borrow_mut().push(diagnostic));
+            Ok("«error»".to_owned())
+        }
+        res => res,
+    }
+}
+
+fn warn(msg: &str) {
+    WARNINGS.with(|warnings| {
+        warnings
+            .borrow_mut()
+            .push((msg.to_owned(), current_origin()))
+    });
+    eprintln!("warning: {}", msg);
+}
+
+/// The number of warnings printed on this thread since the last call to [`eval_str`],
+/// including those printed while forcing its result
+pub fn warning_count() -> usize {
+    WARNINGS.with(|warnings| warnings.borrow().len())
+}
+
+thread_local! {
+    // Number of -v flags passed on the command line
+    static VERBOSITY: Cell<u8> = const { Cell::new(0) };
+}
+
+// Print a diagnostic to stderr if running with at least `level` -v flags. The
+// message is only built when it will be printed
+fn debug(level: u8, msg: impl FnOnce() -> String) {
+    if VERBOSITY.with(Cell::get) >= level {
+        eprintln!("debug: {}", msg());
+    }
+}
+
+fn has_type(attrs: &BTreeMap<String, NixObject>, ty: &str) -> color_eyre::Result<bool> {
+    Ok(match attrs.get("_type") {
+        Some(t) => {
+            matches!(t.force()?, NixObject::Str(t) | NixObject::DiscardedStr(t, _) if t == ty)
+        }
+        None => false,
+    })
+}
+
+// Record the declarations in a module's `options` so `config` lookups can resolve to them
+fn collect_options(
+    prefix: &mut Vec<String>,
+    options: &BTreeMap<String, NixObject>,
+    declarations: &mut HashMap<Vec<String>, NixSet>,
+) -> color_eyre::Result<()> {
+    for (name, val) in options.iter() {
+        let NixObject::Set(NixSet::Dyn(attrs)) = val.force()? else {
+            continue;
+        };
+        prefix.push(name.clone());
+        if has_type(&attrs, "option")? {
+            declarations.insert(prefix.clone(), NixSet::Dyn(attrs));
+        } else {
+            collect_options(prefix, &attrs, declarations)?;
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+// The option declarations of a module, found before its body is evaluated, since the body
+// may read `config` right away, as in `if config.foo.enable then { … } else { … }`. The
+// attribute sets the body is made of are found through lets, withs, `//`, both branches
+// of ifs and function arguments like those of lib.mkIf, without evaluating the rest
+fn declare_module_options(scope: &Scope, body: Expr) {
+    let mut declarations = HashMap::new();
+    // Anything that fails here fails again when the body is evaluated, which reports it
+    let _ = module_declarations(scope, body, &mut declarations);
+    CONFIG.with(|config| config.borrow_mut().declarations = declarations);
+}
+
+fn module_declarations(
+    scope: &Scope,
+    expr: Expr,
+    declarations: &mut HashMap<Vec<String>, NixSet>,
+) -> color_eyre::Result<()> {
+    match expr {
+        Expr::Paren(p) => {
+            if let Some(expr) = p.expr() {
+                module_declarations(scope, expr, declarations)?;
+            }
+        }
+        Expr::LetIn(letin) => {
+            let mut new_scope = scope.clone();
+            let bindings = eval_bindings(scope, letin.entries(), true)?;
+            Rc::make_mut(&mut new_scope.items).extend(bindings);
+            if let Some(body) = letin.body() {
+                module_declarations(&new_scope, body, declarations)?;
+            }
+        }
+        Expr::With(with) => {
+            let (Some(namespace), Some(body)) = (with.namespace(), with.body()) else {
+                return Ok(());
+            };
+            let mut new_scope = scope.clone();
+            Rc::make_mut(&mut new_scope.with_namespaces)
+                .push(eval_object(scope, namespace)?.try_into_set()?);
+            module_declarations(&new_scope, body, declarations)?;
+        }
+        Expr::BinOp(binop) if binop.operator() == Some(BinOpKind::Update) => {
+            for side in [binop.lhs(), binop.rhs()].into_iter().flatten() {
+                module_declarations(scope, side, declarations)?;
+            }
+        }
+        Expr::IfElse(ifelse) => {
+            for branch in [ifelse.body(), ifelse.else_body()].into_iter().flatten() {
+                module_declarations(scope, branch, declarations)?;
+            }
+        }
+        Expr::Apply(apply) => {
+            if let Some(argument) = apply.argument() {
+                module_declarations(scope, argument, declarations)?;
+            }
+        }
+        Expr::List(list) => {
+            for item in list.items() {
+                module_declarations(scope, item, declarations)?;
+            }
+        }
+        Expr::AttrSet(_) => {
+            let NixObject::Set(NixSet::Dyn(module)) = eval_object(scope, expr)? else {
+                return Ok(());
+            };
+            if let Some(options) = module.get("options") {
+                let options = expect_attrs(options, "options")?;
+                collect_options(&mut Vec::new(), &options, declarations)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn json_to_nix(value: serde_json::Value) -> NixObject {
+    use serde_json::Value;
+    match value {
+        Value::Null => NixObject::Null,
+        Value::Bool(b) => NixObject::Bool(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => NixObject::Int(i),
+            None => NixObject::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => NixObject::Str(s),
+        Value::Array(a) => NixObject::List(Rc::new(a.into_iter().map(json_to_nix).collect())),
+        Value::Object(o) => NixObject::Set(NixSet::Dyn(Rc::new(
+            o.into_iter().map(|(k, v)| (k, json_to_nix(v))).collect(),
+        ))),
+    }
+}
+
+fn toml_to_nix(value: toml::Value) -> color_eyre::Result<NixObject> {
+    use toml::Value;
+    Ok(match value {
+        Value::Boolean(b) => NixObject::Bool(b),
+        Value::Integer(i) => NixObject::Int(i),
+        Value::Float(f) => NixObject::Float(f),
+        Value::String(s) => NixObject::Str(s),
+        // Like Nix without the parse-toml-timestamps experimental feature
+        Value::Datetime(_) => {
+            return Err(EvalError::InvalidArgument(
+                "dates and times are not supported in fromTOML".to_owned(),
+            )
+            .into())
+        }
+        Value::Array(a) => NixObject::List(Rc::new(
+            a.into_iter()
+                .map(toml_to_nix)
+                .collect::<color_eyre::Result<_>>()?,
+        )),
+        Value::Table(t) => NixObject::Set(NixSet::Dyn(Rc::new(
+            t.into_iter()
+                .map(|(k, v)| Ok((k, toml_to_nix(v)?)))
+                .collect::<color_eyre::Result<_>>()?,
+        ))),
+    })
+}
+
+fn from_toml(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let table = toml::from_str::<toml::Table>(&args[0].clone().try_into_string()?)
+        .map_err(|err| EvalError::InvalidArgument(format!("while parsing TOML: {}", err)))?;
+    toml_to_nix(toml::Value::Table(table))
+}
+
+fn expect_attrs(
+    val: &NixObject,
+    fn_name: &str,
+) -> color_eyre::Result<Rc<BTreeMap<String, NixObject>>> {
+    match val.clone().try_into_set()? {
+        NixSet::Dyn(attrs) => Ok(attrs),
+        _ => type_error(format!("{} expects a concrete set", fn_name)),
+    }
+}
+
+fn function_args(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    match args[0].force()? {
+        NixObject::Lambda(f) => match f.lambda.param() {
+            Some(Param::Pattern(pat)) => {
+                let mut formals = BTreeMap::new();
+                for entry in pat.pat_entries() {
+                    let ident = entry
+                        .ident()
+                        .ok_or(EvalError::MalformedAst("pat entry without ident"))?;
+                    formals.insert(
+                        ident.to_string(),
+                        NixObject::Bool(entry.default().is_some()),
+                    );
+                }
+                Ok(NixObject::Set(NixSet::Dyn(Rc::new(formals))))
+            }
+            _ => Ok(NixObject::from_attrs([])),
+        },
+        NixObject::PrimOp(..) => Ok(NixObject::from_attrs([])),
+        v => type_error(format!(
+            "functionArgs expects a function, found {}",
+            v.type_name()
+        )),
+    }
+}
+
+fn throw(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Err(EvalError::Throw(args[0].clone().try_into_string()?).into())
+}
+
+fn abort(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Err(EvalError::Abort(args[0].clone().try_into_string()?).into())
+}
+
+// Force a value and everything it contains, except for the bodies of functions
+fn deep_force(val: &NixObject) -> color_eyre::Result<NixObject> {
+    let val = val.force()?;
+    match &val {
+        NixObject::List(items) => {
+            for item in items.iter() {
+                deep_force(item)?;
+            }
+        }
+        NixObject::Set(NixSet::Dyn(attrs)) => {
+            for attr in attrs.values() {
+                deep_force(attr)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(val)
+}
+
+// The message is only evaluated if forcing the value fails
+fn add_error_context(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    args[1]
+        .force()
+        .map_err(|err| match coerce_to_string(args[0].clone()) {
+            Ok(msg) => err.wrap_err(msg),
+            Err(_) => err,
+        })
+}
+
+fn seq(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    args[0].force()?;
+    Ok(args[1].clone())
+}
+
+fn deep_seq(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    deep_force(&args[0])?;
+    Ok(args[1].clone())
+}
+
+fn force(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    deep_force(&args[0])
+}
+
+// Items are visited breadth-first, and every item is only kept the first time its key is seen
+fn generic_closure(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "genericClosure")?;
+    let start_set = attrs
+        .get("startSet")
+        .ok_or(EvalError::InvalidArgument(
+            "genericClosure requires attribute 'startSet'".to_owned(),
+        ))?
+        .clone()
+        .try_into_list()?;
+    let operator = attrs.get("operator").ok_or(EvalError::InvalidArgument(
+        "genericClosure requires attribute 'operator'".to_owned(),
+    ))?;
+
+    let mut work = start_set.iter().cloned().collect::<VecDeque<_>>();
+    let mut keys = Vec::new();
+    let mut res = Vec::new();
+    while let Some(item) = work.pop_front() {
+        let key = expect_attrs(&item, "genericClosure")?
+            .get("key")
+            .ok_or(EvalError::InvalidArgument(
+                "genericClosure items require attribute 'key'".to_owned(),
+            ))?
+            .force()?;
+        let mut seen = false;
+        for k in keys.iter() {
+            if nix_eq(k, &key)? {
+                seen = true;
+                break;
+            }
+        }
+        if seen {
+            continue;
+        }
+        keys.push(key);
+        work.extend(
+            operator
+                .clone()
+                .apply(item.clone())?
+                .try_into_list()?
+                .iter()
+                .cloned(),
+        );
+        res.push(item);
+    }
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn cat_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let mut res = Vec::new();
+    for item in args[1].clone().try_into_list()?.iter() {
+        if let Some(val) = item.clone().try_into_set()?.lookup(&name) {
+            res.push(val);
+        }
+    }
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn partition(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (mut right, mut wrong) = (Vec::new(), Vec::new());
+    for item in args[1].clone().try_into_list()?.iter() {
+        match args[0].clone().apply(item.clone())? {
+            NixObject::Bool(true) => right.push(item.clone()),
+            NixObject::Bool(false) => wrong.push(item.clone()),
+            v => {
+                return type_error(format!(
+                    "partition predicate returned {} instead of a bool",
+                    v.type_name()
+                ))
+            }
+        }
+    }
+    Ok(NixObject::from_attrs([
+        ("right", NixObject::List(Rc::new(right))),
+        ("wrong", NixObject::List(Rc::new(wrong))),
+    ]))
+}
+
+// Sets are ordered by name, so these agree with each other and with Nix
+fn attr_names(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "attrNames")?;
+    Ok(NixObject::List(Rc::new(
+        attrs.keys().map(|k| NixObject::Str(k.clone())).collect(),
+    )))
+}
+
+fn attr_values(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "attrValues")?;
+    Ok(NixObject::List(Rc::new(attrs.values().cloned().collect())))
+}
+
+fn map_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[1], "mapAttrs")?;
+    let res = attrs
+        .iter()
+        .map(|(k, v)| {
+            let (f, name, v) = (args[0].clone(), NixObject::Str(k.clone()), v.clone());
+            let val = NixObject::lazy(move || f.clone().apply(name.clone())?.apply(v.clone()));
+            (k.clone(), val)
+        })
+        .collect();
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+fn map_attrs_recursive(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    map_leaves(
+        None,
+        args[0].clone(),
+        Vec::new(),
+        &args[1],
+        "mapAttrsRecursive",
+    )
+}
+
+fn map_attrs_recursive_cond(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let cond = Some(args[0].clone());
+    map_leaves(
+        cond,
+        args[1].clone(),
+        Vec::new(),
+        &args[2],
+        "mapAttrsRecursiveCond",
+    )
+}
+
+// Apply `f` to the path and value of every attribute that isn't a set, or is a set that
+// `cond` says not to descend into
+fn map_leaves(
+    cond: Option<NixObject>,
+    f: NixObject,
+    path: Vec<String>,
+    set: &NixObject,
+    fn_name: &'static str,
+) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(set, fn_name)?;
+    let res = attrs
+        .iter()
+        .map(|(k, v)| {
+            let (cond, f, v) = (cond.clone(), f.clone(), v.clone());
+            let mut path = path.clone();
+            path.push(k.clone());
+            let val = NixObject::lazy(move || {
+                let descend = match v.force()? {
+                    NixObject::Set(NixSet::Dyn(_)) => match &cond {
+                        Some(cond) => cond.clone().apply(v.clone())?.try_into_bool()?,
+                        None => true,
+                    },
+                    _ => false,
+                };
+                if descend {
+                    map_leaves(cond.clone(), f.clone(), path.clone(), &v, fn_name)
+                } else {
+                    let path = path.iter().cloned().map(NixObject::Str).collect();
+                    f.clone()
+                        .apply(NixObject::List(Rc::new(path)))?
+                        .apply(v.clone())
+                }
+            });
+            (k.clone(), val)
+        })
+        .collect();
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+// Same as nixpkgs, which passes the results to listToAttrs in the order of the names, so
+// the first of several attributes renamed to the same name wins
+fn map_attrs_renamed(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[1], "mapAttrs'")?;
+    let pairs = attrs
+        .iter()
+        .map(|(k, v)| {
+            let (f, name, v) = (args[0].clone(), NixObject::Str(k.clone()), v.clone());
+            NixObject::lazy(move || f.clone().apply(name.clone())?.apply(v.clone()))
+        })
+        .collect();
+    list_to_attrs(&[NixObject::List(Rc::new(pairs))])
+}
+
+fn gen_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut res = BTreeMap::new();
+    for name in args[0].clone().try_into_list()?.iter() {
+        let name = match name.force()? {
+            NixObject::Str(name) | NixObject::DiscardedStr(name, _) => name,
+            v => {
+                return type_error(format!(
+                    "genAttrs expects a list of strings, found {}",
+                    v.type_name()
+                ))
+            }
+        };
+        let (f, arg) = (args[1].clone(), NixObject::Str(name.clone()));
+        res.entry(name)
+            .or_insert_with(|| NixObject::lazy(move || f.clone().apply(arg.clone())));
+    }
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+fn attr_path(val: &NixObject) -> color_eyre::Result<Vec<String>> {
+    val.clone()
+        .try_into_list()?
+        .iter()
+        .map(|name| name.clone().try_into_string())
+        .collect()
+}
+
+// Anything in the way that isn't a set counts as missing too
+fn lookup_attr_path(val: &NixObject, path: &NixObject) -> color_eyre::Result<Option<NixObject>> {
+    let mut val = val.clone();
+    for name in attr_path(path)? {
+        let NixObject::Set(set) = val.force()? else {
+            return Ok(None);
+        };
+        match set.lookup(&name) {
+            Some(next) => val = next,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(val))
+}
+
+fn attr_by_path(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(lookup_attr_path(&args[2], &args[0])?.unwrap_or_else(|| args[1].clone()))
+}
+
+fn has_attr_by_path(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Bool(
+        lookup_attr_path(&args[1], &args[0])?.is_some(),
+    ))
+}
+
+fn set_attr_by_path(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let path = attr_path(&args[0])?;
+    Ok(path.iter().rev().fold(args[1].clone(), |val, name| {
+        NixObject::from_attrs([(name.as_str(), val)])
+    }))
+}
+
+// The values of every name across the sets, in the order of the sets
+fn zip_values(sets: &NixObject) -> color_eyre::Result<BTreeMap<String, Vec<NixObject>>> {
+    let mut values = BTreeMap::<_, Vec<_>>::new();
+    for set in sets.clone().try_into_list()?.iter() {
+        for (k, v) in expect_attrs(set, "zipAttrsWith")?.iter() {
+            values.entry(k.clone()).or_default().push(v.clone());
+        }
+    }
+    Ok(values)
+}
+
+fn zip_attrs_with(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let res = zip_values(&args[1])?
+        .into_iter()
+        .map(|(k, vals)| {
+            let (f, name, vals) = (
+                args[0].clone(),
+                NixObject::Str(k.clone()),
+                NixObject::List(Rc::new(vals)),
+            );
+            let val = NixObject::lazy(move || f.clone().apply(name.clone())?.apply(vals.clone()));
+            (k, val)
+        })
+        .collect();
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+fn zip_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let res = zip_values(&args[0])?
+        .into_iter()
+        .map(|(k, vals)| (k, NixObject::List(Rc::new(vals))))
+        .collect();
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+// Symbolic sets are expanded where their attributes are known
+fn serialized_attrs(
+    set: NixSet,
+    format: &str,
+) -> color_eyre::Result<Rc<BTreeMap<String, NixObject>>> {
+    let desc = describe_set(&set);
+    match materialize(set)? {
+        Some(attrs) => Ok(attrs),
+        None => type_error(format!("cannot convert {} to {}", desc, format)),
+    }
+}
+
+// Same as Nix, which ignores a single trailing slash
+fn base_name(path: &str) -> &str {
+    let path = match path.strip_suffix('/') {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => path,
+    };
+    match path.rfind('/') {
+        Some(pos) => &path[pos + 1..],
+        None => path,
+    }
+}
+
+// Unlike base_name, a trailing slash is part of the directory
+fn dir_name(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) => "/",
+        Some(pos) => &path[..pos],
+        None => ".",
+    }
+}
+
+fn base_name_of(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(
+        base_name(&coerce_to_string(args[0].clone())?).to_owned(),
+    ))
+}
+
+// Paths stay paths, anything else is treated as a string
+fn dir_of(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(match args[0].force()? {
+        NixObject::Path(path) => NixObject::Path(dir_name(&path).to_owned()),
+        v => NixObject::Str(dir_name(&coerce_to_string(v)?).to_owned()),
+    })
+}
+
+// Falls back to the name Nix uses when the path has none of its own
+fn source_name(path: &str) -> String {
+    match base_name(path) {
+        "" | "." | ".." => "source".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+// Filtering a source again keeps its original path, like lib.cleanSourceWith does
+fn to_source(val: &NixObject) -> color_eyre::Result<Source> {
+    match val.force()? {
+        NixObject::Path(path) | NixObject::Str(path) | NixObject::DiscardedStr(path, _) => {
+            Ok(Source {
+                name: source_name(&path),
+                path,
+                filters: Vec::new(),
+            })
+        }
+        NixObject::Source(source) => Ok((*source).clone()),
+        v => type_error(format!("expected a path, found {}", v.type_name())),
+    }
+}
+
+fn path(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "path")?;
+    let mut source = to_source(attrs.get("path").ok_or(EvalError::InvalidArgument(
+        "path requires attribute 'path'".to_owned(),
+    ))?)?;
+    if let Some(name) = attrs.get("name") {
+        source.name = name.clone().try_into_string()?;
+    }
+    if let Some(filter) = attrs.get("filter") {
+        source.filters.push(SourceFilter::Custom(filter.clone()));
+    }
+    Ok(NixObject::Source(Rc::new(source)))
+}
+
+fn filter_source(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut source = to_source(&args[1])?;
+    source.filters.push(SourceFilter::Custom(args[0].clone()));
+    Ok(NixObject::Source(Rc::new(source)))
+}
+
+fn clean_source(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut source = to_source(&args[0])?;
+    source.filters.push(SourceFilter::Clean);
+    Ok(NixObject::Source(Rc::new(source)))
+}
+
+fn source_by_regex(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut source = to_source(&args[0])?;
+    let regexes = args[1]
+        .clone()
+        .try_into_list()?
+        .iter()
+        .map(|r| r.clone().try_into_string())
+        .collect::<color_eyre::Result<_>>()?;
+    source.filters.push(SourceFilter::Regexes(regexes));
+    Ok(NixObject::Source(Rc::new(source)))
+}
+
+fn nix_to_json(val: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    Ok(match val.force()? {
+        NixObject::Null => serde_json::Value::Null,
+        NixObject::Bool(b) => serde_json::Value::Bool(b),
+        NixObject::Int(i) => serde_json::Value::from(i),
+        NixObject::Float(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .ok_or(EvalError::TypeError(format!(
+                "cannot convert {} to JSON",
+                f
+            )))?,
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => serde_json::Value::String(s),
+        v @ (NixObject::Path(_) | NixObject::Source(_)) => {
+            serde_json::Value::String(coerce_to_string(v)?)
+        }
+        NixObject::List(items) => serde_json::Value::Array(
+            items
+                .iter()
+                .map(nix_to_json)
+                .collect::<color_eyre::Result<_>>()?,
+        ),
+        NixObject::Set(set) => match serialized_attrs(set, "JSON")? {
+            attrs if attrs.contains_key("outPath") => {
+                serde_json::Value::String(coerce_to_string(attrs["outPath"].clone())?)
+            }
+            // serde_json objects are ordered by key, like Nix's
+            attrs => serde_json::Value::Object(
+                attrs
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), nix_to_json(v)?)))
+                    .collect::<color_eyre::Result<_>>()?,
+            ),
+        },
+        v => return type_error(format!("cannot convert {} to JSON", v.type_name())),
+    })
+}
+
+fn to_json(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(nix_to_json(&args[0])?.to_string()))
+}
+
+thread_local! {
+    // Whether values that can't be known are left unknown instead of being errors
+    static LENIENT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn lenient() -> bool {
+    LENIENT.with(Cell::get)
+}
+
+thread_local! {
+    // Whether builtins that depend on the machine running the evaluation may do so
+    static IMPURE: Cell<bool> = const { Cell::new(false) };
+    // How many times the environment has been read, so imports that do it eagerly aren't
+    // cached
+    static IMPURE_READS: Cell<usize> = const { Cell::new(0) };
+    // Whether importing from a derivation gives an unknown value instead of failing
+    static ALLOW_IFD: Cell<bool> = const { Cell::new(false) };
+}
+
+// The environment is only read in impure mode, otherwise the variable is left unknown
+// rather than pretending it is unset like pure Nix does, so analysis can tell that the
+// result depends on it
+fn get_env(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    if !IMPURE.with(Cell::get) {
+        return Ok(NixObject::Unknown(format!(
+            "builtins.getEnv {}",
+            quote_string(&name)
+        )));
+    }
+    IMPURE_READS.with(|reads| reads.set(reads.get() + 1));
+    Ok(NixObject::Str(std::env::var(&name).unwrap_or_default()))
+}
+
+const HASH_ALGORITHMS: [&str; 4] = ["md5", "sha1", "sha256", "sha512"];
+
+// The hex digest of `data` with one of HASH_ALGORITHMS
+fn hash_hex(algo: &str, data: &[u8]) -> color_eyre::Result<String> {
+    use sha2::Digest;
+    let digest = match algo {
+        "md5" => md5::Md5::digest(data).to_vec(),
+        "sha1" => sha1::Sha1::digest(data).to_vec(),
+        "sha256" => sha2::Sha256::digest(data).to_vec(),
+        "sha512" => sha2::Sha512::digest(data).to_vec(),
+        _ => {
+            return Err(EvalError::InvalidArgument(format!(
+                "unknown hash algorithm '{}', expected one of {}",
+                algo,
+                HASH_ALGORITHMS.join(", ")
+            ))
+            .into())
+        }
+    };
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn hash_string(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let algo = args[0].clone().try_into_string()?;
+    let s = args[1].clone().try_into_string()?;
+    Ok(NixObject::Str(hash_hex(&algo, s.as_bytes())?))
+}
+
+// Relative paths are read from the working directory
+fn hash_file(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let algo = args[0].clone().try_into_string()?;
+    let path = coerce_to_string(args[1].clone())?;
+    let data = std::fs::read(&path).wrap_err_with(|| format!("while hashing {}", path))?;
+    Ok(NixObject::Str(hash_hex(&algo, &data)?))
+}
+
+thread_local! {
+    // The entries of the Nix search path as prefix and path, where the prefix may be empty
+    static NIX_PATH: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+// Parse a search path like NIX_PATH, as in `nixpkgs=/src/nixpkgs:/other`. Colons that
+// are part of a URL don't separate entries
+fn parse_nix_path(nix_path: &str) -> Vec<(String, String)> {
+    let mut entries: Vec<String> = Vec::new();
+    for part in nix_path.split(':') {
+        match entries.last_mut() {
+            Some(last) if part.starts_with("//") => {
+                last.push(':');
+                last.push_str(part);
+            }
+            _ if part.is_empty() => {}
+            _ => entries.push(part.to_owned()),
+        }
+    }
+    entries
+        .into_iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((prefix, path)) => (prefix.to_owned(), path.to_owned()),
+            None => (String::new(), entry),
+        })
+        .collect()
+}
+
+// Like Nix, the first entry under which the path exists wins
+fn resolve_search_path(search_path: &str) -> color_eyre::Result<NixObject> {
+    let found = NIX_PATH.with(|nix_path| {
+        nix_path.borrow().iter().find_map(|(prefix, path)| {
+            let rest = if prefix.is_empty() {
+                Some(format!("/{}", search_path))
+            } else if search_path == prefix {
+                Some(String::new())
+            } else {
+                search_path
+                    .strip_prefix(prefix.as_str())
+                    .filter(|rest| rest.starts_with('/'))
+                    .map(str::to_owned)
+            }?;
+            let candidate = format!("{}{}", path.trim_end_matches('/'), rest);
+            Path::new(&candidate).exists().then_some(candidate)
+        })
+    });
+    match found {
+        Some(path) => Ok(NixObject::Path(path)),
+        None => Err(EvalError::InvalidArgument(format!(
+            "file '{}' was not found in the Nix search path",
+            search_path
+        ))
+        .into()),
+    }
+}
+
+thread_local! {
+    // The values of the files imported since the evaluation started, by canonical path
+    static IMPORTS: RefCell<HashMap<std::path::PathBuf, NixObject>> =
+        RefCell::new(HashMap::new());
+    // The syntax trees of the evaluated files and the directories they are in, which the
+    // relative paths in them are resolved against
+    static IMPORT_DIRS: RefCell<Vec<(rnix::SyntaxNode, String)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+// Like Nix, every file is only evaluated once however often it is imported. Imports that
+// read the environment while they are evaluated are evaluated again, as they may differ.
+// Only reads made while evaluating the file to its outermost value are noticed: the value
+// is cached before the thunks inside it are forced, so reads in those are made once and
+// shared by every import of the file
+fn import(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut path = coerce_to_string(args[0].clone())?;
+    // Files written by builtins.toFile are in the store right away, so they can be
+    // imported. They are not cached, as their path doesn't depend on their content here
+    if let Some(content) = written_file(&path) {
+        return eval_import(&path, &content);
+    }
+    // The outputs of derivations only exist once they are built, which Nix does in the
+    // middle of the evaluation. This includes the files written by builders like writeText
+    if [
+        STORE_PLACEHOLDER,
+        OUTPUT_PLACEHOLDER,
+        FILE_PLACEHOLDER,
+        BUILT_PLACEHOLDER,
+    ]
+    .iter()
+    .any(|placeholder| path.starts_with(placeholder))
+    {
+        if !ALLOW_IFD.with(Cell::get) {
+            return Err(EvalError::InvalidArgument(format!(
+                "cannot import {}, which would have to be built first (import from derivation)",
+                path
+            ))
+            .into());
+        }
+        warn(&format!("import from derivation: {}", path));
+        return Ok(NixObject::Unknown(format!("import {}", path)));
+    }
+    if Path::new(&path).is_dir() {
+        path = format!("{}/default.nix", path.trim_end_matches('/'));
+    }
+    let canonical =
+        std::fs::canonicalize(&path).wrap_err_with(|| format!("while importing {}", path))?;
+    if let Some(val) = IMPORTS.with(|imports| imports.borrow().get(&canonical).cloned()) {
+        return Ok(val);
+    }
+    let source = std::fs::read_to_string(&canonical)
+        .wrap_err_with(|| format!("while importing {}", path))?;
+    let reads = IMPURE_READS.with(Cell::get);
+    let val = eval_import(&path, &source)?;
+    if IMPURE_READS.with(Cell::get) == reads {
+        IMPORTS.with(|imports| imports.borrow_mut().insert(canonical, val.clone()));
+    }
+    Ok(val)
+}
+
+/// The canonical paths of the files imported on this thread since the last call to
+/// [`eval_str`], including while forcing its result
+pub fn imported_files() -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = IMPORTS.with(|imports| imports.borrow().keys().cloned().collect());
+    files.sort();
+    files
+}
+
+// The content of the file at `path` if builtins.toFile wrote it
+fn written_file(path: &str) -> Option<String> {
+    FILES.with(|files| {
+        files
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(file, _, _, built)| file == path && !built)
+            .map(|(_, _, content, _)| content.clone())
+    })
+}
+
+fn eval_import(path: &str, source: &str) -> color_eyre::Result<NixObject> {
+    let ast = rnix::Root::parse(source)
+        .ok()
+        .wrap_err_with(|| format!("while parsing {}", path))?;
+    let dir = dir_name(path).to_owned();
+    IMPORT_DIRS.with(|dirs| dirs.borrow_mut().push((ast.syntax().clone(), dir)));
+    eval_object(
+        &Scope::new(),
+        ast.expr().ok_or_else(|| eyre!("{} is empty", path))?,
+    )?
+    .force()
+}
+
+// The directory of the file that `expr` is part of, if it is from a file
+fn import_dir(expr: &Expr) -> Option<String> {
+    IMPORT_DIRS.with(|dirs| {
+        let dirs = dirs.borrow();
+        if dirs.is_empty() {
+            return None;
+        }
+        let root = expr.syntax().ancestors().last()?;
+        dirs.iter()
+            .find(|(file, _)| *file == root)
+            .map(|(_, dir)| dir.clone())
+    })
+}
+
+fn nix_path() -> NixObject {
+    NIX_PATH.with(|nix_path| {
+        NixObject::List(Rc::new(
+            nix_path
+                .borrow()
+                .iter()
+                .map(|(prefix, path)| {
+                    NixObject::from_attrs([
+                        ("prefix", NixObject::Str(prefix.clone())),
+                        ("path", NixObject::Str(path.clone())),
+                    ])
+                })
+                .collect(),
+        ))
+    })
+}
+
+// https://github.com/NixOS/nix/blob/master/src/libstore/names.cc
+// The next component of a version: a run of digits, or of anything but digits and the
+// `.` and `-` separators. It is empty at the end
+fn next_version_component<'a>(rest: &mut &'a str) -> &'a str {
+    *rest = rest.trim_start_matches(['.', '-']);
+    let is_digit = rest.starts_with(|c: char| c.is_ascii_digit());
+    let end = rest
+        .find(|c: char| {
+            if is_digit {
+                !c.is_ascii_digit()
+            } else {
+                c.is_ascii_digit() || c == '.' || c == '-'
+            }
+        })
+        .unwrap_or(rest.len());
+    let (component, next) = rest.split_at(end);
+    *rest = next;
+    component
+}
+
+// Numbers compare numerically, `pre` comes before anything else and other words come
+// before numbers
+fn version_component_lt(c1: &str, c2: &str) -> bool {
+    match (c1.parse::<u64>().ok(), c2.parse::<u64>().ok()) {
+        (Some(n1), Some(n2)) => n1 < n2,
+        (_, Some(_)) if c1.is_empty() => true,
+        _ if c1 == "pre" && c2 != "pre" => true,
+        _ if c2 == "pre" => false,
+        (_, Some(_)) => true,
+        (Some(_), _) => false,
+        _ => c1 < c2,
+    }
+}
+
+fn compare_version_strings(v1: &str, v2: &str) -> Ordering {
+    let (mut p1, mut p2) = (v1, v2);
+    while !p1.is_empty() || !p2.is_empty() {
+        let c1 = next_version_component(&mut p1);
+        let c2 = next_version_component(&mut p2);
+        if version_component_lt(c1, c2) {
+            return Ordering::Less;
+        } else if version_component_lt(c2, c1) {
+            return Ordering::Greater;
+        }
+    }
+    Ordering::Equal
+}
+
+fn compare_versions(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let v1 = args[0].clone().try_into_string()?;
+    let v2 = args[1].clone().try_into_string()?;
+    Ok(NixObject::Int(compare_version_strings(&v1, &v2) as i64))
+}
+
+fn split_version(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let version = args[0].clone().try_into_string()?;
+    let mut rest = version.as_str();
+    let mut components = Vec::new();
+    while !rest.is_empty() {
+        let component = next_version_component(&mut rest);
+        if component.is_empty() {
+            break;
+        }
+        components.push(NixObject::Str(component.to_owned()));
+    }
+    Ok(NixObject::List(Rc::new(components)))
+}
+
+fn version_older(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let v1 = args[0].clone().try_into_string()?;
+    let v2 = args[1].clone().try_into_string()?;
+    Ok(NixObject::Bool(compare_version_strings(&v1, &v2).is_lt()))
+}
+
+fn version_at_least(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let v1 = args[0].clone().try_into_string()?;
+    let v2 = args[1].clone().try_into_string()?;
+    Ok(NixObject::Bool(compare_version_strings(&v1, &v2).is_ge()))
+}
+
+// Like Nix, the version starts after the first dash that isn't followed by a letter, so
+// "nix-unstable-2.3" is nix-unstable at version 2.3
+fn split_drv_name(s: &str) -> (&str, &str) {
+    let bytes = s.as_bytes();
+    (0..bytes.len())
+        .find(|&i| bytes[i] == b'-' && bytes.get(i + 1).is_some_and(|c| !c.is_ascii_alphabetic()))
+        .map_or((s, ""), |i| (&s[..i], &s[i + 1..]))
+}
+
+fn parse_drv_name(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let s = args[0].clone().try_into_string()?;
+    let (name, version) = split_drv_name(&s);
+    Ok(NixObject::from_attrs([
+        ("name", NixObject::Str(name.to_owned())),
+        ("version", NixObject::Str(version.to_owned())),
+    ]))
+}
+
+// lib.getName and lib.getVersion take a name string or a derivation, which may have the
+// part asked for as an attribute of its own
+fn drv_name_part(
+    val: &NixObject,
+    attr: &str,
+    fn_name: &str,
+    part: for<'a> fn((&'a str, &'a str)) -> &'a str,
+) -> color_eyre::Result<NixObject> {
+    match val.force()? {
+        NixObject::Str(s) => Ok(NixObject::Str(part(split_drv_name(&s)).to_owned())),
+        // Packages from pkgs are only known by their attribute name
+        NixObject::Nixpkg(name) if attr == "pname" => Ok(NixObject::Str(name)),
+        NixObject::Nixpkg(name) => Ok(NixObject::Unknown(format!("pkgs.{}.{}", name, attr))),
+        v => {
+            let attrs = expect_attrs(&v, fn_name)?;
+            if let Some(val) = attrs.get(attr) {
+                return val.force();
+            }
+            let name = attrs
+                .get("name")
+                .ok_or_else(|| {
+                    EvalError::InvalidArgument(format!(
+                        "{} requires a string or a set with 'name'",
+                        fn_name
+                    ))
+                })?
+                .clone()
+                .try_into_string()?;
+            Ok(NixObject::Str(part(split_drv_name(&name)).to_owned()))
+        }
+    }
+}
+
+fn get_name(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    drv_name_part(&args[0], "pname", "getName", |(name, _)| name)
+}
+
+fn get_version(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    drv_name_part(&args[0], "version", "getVersion", |(_, version)| version)
+}
+
+fn optional(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let list = if args[0].clone().try_into_bool()? {
+        vec![args[1].clone()]
+    } else {
+        Vec::new()
+    };
+    Ok(NixObject::List(Rc::new(list)))
+}
+
+fn optionals(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    if args[0].clone().try_into_bool()? {
+        return args[1].force();
+    }
+    Ok(NixObject::List(Rc::new(Vec::new())))
+}
+
+fn int_args(args: &[NixObject], fn_name: &str) -> color_eyre::Result<(i64, i64)> {
+    match (args[0].force()?, args[1].force()?) {
+        (NixObject::Int(a), NixObject::Int(b)) => Ok((a, b)),
+        (a, b) => type_error(format!(
+            "{} expects two integers, found {} and {}",
+            fn_name,
+            a.type_name(),
+            b.type_name()
+        )),
+    }
+}
+
+fn bit_and(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = int_args(args, "bitAnd")?;
+    Ok(NixObject::Int(a & b))
+}
+
+fn bit_or(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = int_args(args, "bitOr")?;
+    Ok(NixObject::Int(a | b))
+}
+
+fn bit_xor(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = int_args(args, "bitXor")?;
+    Ok(NixObject::Int(a ^ b))
+}
+
+// Integers are passed through, and floats must be in the range of an integer after
+// rounding
+fn round_with(val: &NixObject, round: fn(f64) -> f64) -> color_eyre::Result<NixObject> {
+    match val.force()? {
+        NixObject::Int(i) => Ok(NixObject::Int(i)),
+        NixObject::Float(f) => {
+            let rounded = round(f);
+            // i64::MAX as f64 is 2^63, which is one past the largest integer
+            if !(i64::MIN as f64..i64::MAX as f64).contains(&rounded) {
+                return type_error(format!("cannot convert {} to an integer", f));
+            }
+            Ok(NixObject::Int(rounded as i64))
+        }
+        v => type_error(format!("expected a number, found {}", v.type_name())),
+    }
+}
+
+fn ceil(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    round_with(&args[0], f64::ceil)
+}
+
+fn floor(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    round_with(&args[0], f64::floor)
+}
+
+// `op acc x` for each element from the left. This is strict in the accumulator like
+// builtins.foldl', which is fine for lib.foldl as long as the accumulator is used
+fn foldl(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut acc = args[1].force()?;
+    for item in args[2].clone().try_into_list()?.iter() {
+        acc = args[0].clone().apply(acc)?.apply(item.clone())?;
+    }
+    Ok(acc)
+}
+
+// `op x acc` for each element from the right, so unlike foldl the element comes first
+fn foldr(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut acc = args[1].clone();
+    for item in args[2].clone().try_into_list()?.iter().rev() {
+        acc = args[0].clone().apply(item.clone())?.apply(acc)?;
+    }
+    Ok(acc)
+}
+
+fn pipe(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut val = args[0].clone();
+    for (i, f) in args[1].clone().try_into_list()?.iter().enumerate() {
+        let f = f.force()?;
+        if !matches!(f, NixObject::Lambda(_) | NixObject::PrimOp(..)) {
+            return type_error(format!(
+                "pipe expects a list of functions, found {} at position {}",
+                f.type_name(),
+                i
+            ));
+        }
+        val = f.apply(val)?;
+    }
+    Ok(val)
+}
+
+fn flip(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    args[0]
+        .clone()
+        .apply(args[2].clone())?
+        .apply(args[1].clone())
+}
+
+fn const_(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(args[0].clone())
+}
+
+// The result is passed to `f` as a thunk of itself, so `f` may refer to any of its
+// attributes as long as it doesn't need them to construct the set
+fn fix(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let f = args[0].clone();
+    let this = Rc::new(OnceCell::<NixObject>::new());
+    let val = {
+        let this = this.clone();
+        NixObject::lazy(move || {
+            f.clone()
+                .apply(this.get().expect("fixed point is set").clone())
+        })
+    };
+    this.set(val.clone()).expect("fixed point is only set once");
+    val.force()
+}
+
+// Like `super // f self super` where `super` is what `rattrs` makes of `self`
+fn extends(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (f, rattrs, this) = (args[0].clone(), args[1].clone(), args[2].clone());
+    let prev = rattrs.apply(this.clone())?;
+    let overrides = expect_attrs(&f.apply(this)?.apply(prev.clone())?, "extends")?;
+    let mut merged = (*expect_attrs(&prev, "extends")?).clone();
+    merged.extend(overrides.iter().map(|(k, v)| (k.clone(), v.clone())));
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(merged))))
+}
+
+fn make_extensible(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let rattrs = args[0].clone();
+    fix(&[NixObject::PrimOp(&EXTENSIBLE, vec![rattrs])])
+}
+
+fn extensible(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (rattrs, this) = (args[0].clone(), args[1].clone());
+    let mut attrs = (*expect_attrs(&rattrs.clone().apply(this)?, "makeExtensible")?).clone();
+    attrs.insert(
+        "extend".to_owned(),
+        NixObject::PrimOp(&EXTEND, vec![rattrs]),
+    );
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(attrs))))
+}
+
+fn extend(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (rattrs, f) = (args[0].clone(), args[1].clone());
+    make_extensible(&[NixObject::PrimOp(&LIB["extends"], vec![f, rattrs])])
+}
+
+// Only results that are sets can carry `override`, others are returned as they are
+fn make_overridable(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (f, orig_args) = (args[0].clone(), args[1].clone());
+    let result = f.clone().apply(orig_args.clone())?;
+    let NixObject::Set(NixSet::Dyn(attrs)) = result else {
+        return Ok(result);
+    };
+    let mut attrs = (*attrs).clone();
+    attrs.insert(
+        "override".to_owned(),
+        NixObject::PrimOp(&OVERRIDE, vec![f, orig_args]),
+    );
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(attrs))))
+}
+
+// The new arguments are merged into the original ones, and may also be a function of them
+fn override_(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (f, orig_args) = (args[0].clone(), args[1].clone());
+    let new_args = match args[2].force()? {
+        g @ (NixObject::Lambda(_) | NixObject::PrimOp(..)) => g.apply(orig_args.clone())?,
+        new_args => new_args,
+    };
+    let mut merged = (*expect_attrs(&orig_args, "override")?).clone();
+    let new_args = expect_attrs(&new_args, "override")?;
+    merged.extend(new_args.iter().map(|(k, v)| (k.clone(), v.clone())));
+    make_overridable(&[f, NixObject::Set(NixSet::Dyn(Rc::new(merged)))])
+}
+
+fn concat_lists(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut out = Vec::new();
+    for list in args[0].clone().try_into_list()?.iter() {
+        out.extend(list.clone().try_into_list()?.iter().cloned());
+    }
+    Ok(NixObject::List(Rc::new(out)))
+}
+
+// Stops at the first element the predicate holds for
+fn any(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    for item in args[1].clone().try_into_list()?.iter() {
+        if args[0].clone().apply(item.clone())?.try_into_bool()? {
+            return Ok(NixObject::Bool(true));
+        }
+    }
+    Ok(NixObject::Bool(false))
+}
+
+// Stops at the first element the predicate does not hold for
+fn all(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    for item in args[1].clone().try_into_list()?.iter() {
+        if !args[0].clone().apply(item.clone())?.try_into_bool()? {
+            return Ok(NixObject::Bool(false));
+        }
+    }
+    Ok(NixObject::Bool(true))
+}
+
+// Values come from the second set. Either set can be symbolic as long as the other one is
+// not, so `intersectAttrs (functionArgs f) pkgs` works like it does in callPackage
+fn intersect_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = (
+        args[0].clone().try_into_set()?,
+        args[1].clone().try_into_set()?,
+    );
+    let res = match (materialize(a.clone())?, materialize(b.clone())?) {
+        (_, Some(b)) => b
+            .iter()
+            .filter(|(k, _)| a.lookup(k).is_some())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        (Some(a), None) => a
+            .keys()
+            .filter_map(|k| Some((k.clone(), b.lookup(k)?)))
+            .collect(),
+        (None, None) => return type_error("intersectAttrs expects a concrete set".to_owned()),
+    };
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+// Like `//`, but merges the sets found under the same name on both sides. Only the values
+// present on both sides are forced, and only once they are needed
+fn recursive_update(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut merged = (*expect_attrs(&args[0], "recursiveUpdate")?).clone();
+    for (k, rhs) in expect_attrs(&args[1], "recursiveUpdate")?.iter() {
+        let val = match merged.get(k) {
+            Some(lhs) => {
+                let (lhs, rhs) = (lhs.clone(), rhs.clone());
+                NixObject::lazy(move || match (lhs.force()?, rhs.force()?) {
+                    (
+                        lhs @ NixObject::Set(NixSet::Dyn(_)),
+                        rhs @ NixObject::Set(NixSet::Dyn(_)),
+                    ) => recursive_update(&[lhs, rhs]),
+                    (_, rhs) => Ok(rhs),
+                })
+            }
+            None => rhs.clone(),
+        };
+        merged.insert(k.clone(), val);
+    }
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(merged))))
+}
+
+// Elements keep their order within each group
+fn group_by(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for item in args[1].clone().try_into_list()?.iter() {
+        let key = match args[0].clone().apply(item.clone())?.force()? {
+            NixObject::Str(key) | NixObject::DiscardedStr(key, _) => key,
+            v => {
+                return type_error(format!(
+                    "groupBy expects the function to return a string, found {}",
+                    v.type_name()
+                ))
+            }
+        };
+        groups.entry(key).or_default().push(item.clone());
+    }
+    let res = groups
+        .into_iter()
+        .map(|(k, items)| (k, NixObject::List(Rc::new(items))))
+        .collect();
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+fn concat_map(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut out = Vec::new();
+    for (i, item) in args[1].clone().try_into_list()?.iter().enumerate() {
+        match args[0].clone().apply(item.clone())?.force()? {
+            NixObject::List(items) => out.extend(items.iter().cloned()),
+            v => {
+                return type_error(format!(
+                    "concatMap expects the function to return a list, found {} for element {}",
+                    v.type_name(),
+                    i
+                ))
+            }
+        }
+    }
+    Ok(NixObject::List(Rc::new(out)))
+}
+
+// Like nixpkgs, the combinations are built up one attribute at a time with concatMap, so
+// an attribute without any values leaves no combinations at all
+fn cartesian_product(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "cartesianProductOfSets")?;
+    let mut combinations = NixObject::List(Rc::new(vec![NixObject::from_attrs([])]));
+    for (name, values) in attrs.iter() {
+        let add_value = NixObject::PrimOp(
+            &ADD_COMBINATION_VALUE,
+            vec![NixObject::Str(name.clone()), values.clone()],
+        );
+        combinations = concat_map(&[add_value, combinations])?;
+    }
+    Ok(combinations)
+}
+
+fn add_combination_value(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let combination = expect_attrs(&args[2], "cartesianProductOfSets")?;
+    let values = args[1].clone().try_into_list()?;
+    Ok(NixObject::List(Rc::new(
+        values
+            .iter()
+            .map(|value| {
+                let mut combination = (*combination).clone();
+                combination.insert(name.clone(), value.clone());
+                NixObject::Set(NixSet::Dyn(Rc::new(combination)))
+            })
+            .collect(),
+    )))
+}
+
+// The first list varies slowest, like `crossLists (a: b: [ a b ]) [ [ 1 2 ] [ 3 4 ] ]` gives
+// [ [ 1 3 ] [ 1 4 ] [ 2 3 ] [ 2 4 ] ]
+fn cross_lists(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut results = vec![args[0].clone()];
+    for (i, list) in args[1].clone().try_into_list()?.iter().enumerate() {
+        let list = list.clone().try_into_list().wrap_err_with(|| {
+            format!("crossLists expects a list of lists, element {} is not", i)
+        })?;
+        let mut next = Vec::new();
+        for f in &results {
+            for item in list.iter() {
+                next.push(f.clone().apply(item.clone())?);
+            }
+        }
+        results = next;
+    }
+    Ok(NixObject::List(Rc::new(results)))
+}
+
+// Like in Nix, the longer list is truncated to the length of the shorter one
+fn zip_lists_with(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = (
+        args[1].clone().try_into_list()?,
+        args[2].clone().try_into_list()?,
+    );
+    let res = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| {
+            let (f, x, y) = (args[0].clone(), x.clone(), y.clone());
+            NixObject::lazy(move || f.clone().apply(x.clone())?.apply(y.clone()))
+        })
+        .collect();
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn zip_lists(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (a, b) = (
+        args[0].clone().try_into_list()?,
+        args[1].clone().try_into_list()?,
+    );
+    let res = a
+        .iter()
+        .zip(b.iter())
+        .map(|(fst, snd)| NixObject::from_attrs([("fst", fst.clone()), ("snd", snd.clone())]))
+        .collect();
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn flatten_into(val: &NixObject, out: &mut Vec<NixObject>) -> color_eyre::Result<()> {
+    match val.force()? {
+        NixObject::List(items) => {
+            for item in items.iter() {
+                flatten_into(item, out)?;
+            }
+        }
+        _ => out.push(val.clone()),
+    }
+    Ok(())
+}
+
+// Unlike concatLists, nested lists are flattened at any depth and non-list
+// values are kept
+fn flatten(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut out = Vec::new();
+    flatten_into(&args[0], &mut out)?;
+    Ok(NixObject::List(Rc::new(out)))
+}
+
+fn range(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let from = args[0].clone().try_into_int()?;
+    let to = args[1].clone().try_into_int()?;
+    Ok(NixObject::List(Rc::new(
+        (from..=to).map(NixObject::Int).collect(),
+    )))
+}
+
+// Like nixpkgs, an empty list is an error raised with builtins.throw
+fn last(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    match args[0].clone().try_into_list()?.last() {
+        Some(item) => item.force(),
+        None => Err(EvalError::Throw("lists.last: list must not be empty!".to_owned()).into()),
+    }
+}
+
+fn init(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    match args[0].clone().try_into_list()?.split_last() {
+        Some((_, init)) => Ok(NixObject::List(Rc::new(init.to_vec()))),
+        None => Err(EvalError::Throw("lists.init: list must not be empty!".to_owned()).into()),
+    }
+}
+
+fn map(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let res = args[1]
+        .clone()
+        .try_into_list()?
+        .iter()
+        .map(|item| {
+            let (f, item) = (args[0].clone(), item.clone());
+            NixObject::lazy(move || f.clone().apply(item.clone()))
+        })
+        .collect();
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn count(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut n = 0;
+    for item in args[1].clone().try_into_list()?.iter() {
+        if args[0].clone().apply(item.clone())?.try_into_bool()? {
+            n += 1;
+        }
+    }
+    Ok(NixObject::Int(n))
+}
+
+// Like map, but the function is also given the index of the element, counted from `base`
+fn imap(base: i64, args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let res = args[1]
+        .clone()
+        .try_into_list()?
+        .iter()
+        .zip(base..)
+        .map(|(item, i)| {
+            let (f, item) = (args[0].clone(), item.clone());
+            NixObject::lazy(move || f.clone().apply(NixObject::Int(i))?.apply(item.clone()))
+        })
+        .collect();
+    Ok(NixObject::List(Rc::new(res)))
+}
+
+fn imap0(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    imap(0, args)
+}
+
+fn imap1(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    imap(1, args)
+}
+
+// The first occurrence of a name wins, values are left unevaluated
+fn list_to_attrs(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut res = BTreeMap::new();
+    for item in args[0].clone().try_into_list()?.iter() {
+        let attrs = expect_attrs(item, "listToAttrs")?;
+        let name = attrs
+            .get("name")
+            .ok_or(EvalError::InvalidArgument(
+                "listToAttrs element is missing the name attribute".to_owned(),
+            ))?
+            .clone()
+            .try_into_string()?;
+        let value = attrs.get("value").ok_or(EvalError::InvalidArgument(
+            "listToAttrs element is missing the value attribute".to_owned(),
+        ))?;
+        res.entry(name).or_insert_with(|| value.clone());
+    }
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(res))))
+}
+
+fn name_value_pair(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::from_attrs([
+        ("name", args[0].clone()),
+        ("value", args[1].clone()),
+    ]))
+}
+
+// Alternates the unmatched pieces of `s` with lists of the capture groups of
+// each match, null for groups that did not participate
+fn split_regex(re: &Regex, s: &str) -> Vec<NixObject> {
+    let mut res = Vec::new();
+    let mut last = 0;
+    for caps in re.captures_iter(s) {
+        let m = caps.get(0).unwrap();
+        res.push(NixObject::Str(s[last..m.start()].to_owned()));
+        let groups = caps
+            .iter()
+            .skip(1)
+            .map(|g| g.map_or(NixObject::Null, |g| NixObject::Str(g.as_str().to_owned())))
+            .collect();
+        res.push(NixObject::List(Rc::new(groups)));
+        last = m.end();
+    }
+    res.push(NixObject::Str(s[last..].to_owned()));
+    res
+}
+
+fn split(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let pattern = args[0].clone().try_into_string()?;
+    let re = Regex::new(&pattern)
+        .map_err(|e| EvalError::InvalidArgument(format!("invalid regex '{}': {}", pattern, e)))?;
+    let s = args[1].clone().try_into_string()?;
+    Ok(NixObject::List(Rc::new(split_regex(&re, &s))))
+}
+
+// Same as nixpkgs, which filters the strings out of builtins.split on the
+// escaped separator
+fn split_string(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let sep = coerce_to_string(args[0].clone())?;
+    let s = coerce_to_string(args[1].clone())?;
+    let re = Regex::new(&regex::escape(&sep))?;
+    let parts = split_regex(&re, &s)
+        .into_iter()
+        .filter(|part| matches!(part, NixObject::Str(_) | NixObject::DiscardedStr(..)))
+        .collect();
+    Ok(NixObject::List(Rc::new(parts)))
+}
+
+fn has_prefix(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let prefix = args[0].clone().try_into_string()?;
+    Ok(NixObject::Bool(
+        args[1].clone().try_into_string()?.starts_with(&prefix),
+    ))
+}
+
+fn has_suffix(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let suffix = args[0].clone().try_into_string()?;
+    Ok(NixObject::Bool(
+        args[1].clone().try_into_string()?.ends_with(&suffix),
+    ))
+}
+
+// The string is returned unchanged if it does not have the prefix
+fn remove_prefix(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let prefix = args[0].clone().try_into_string()?;
+    let s = args[1].clone().try_into_string()?;
+    Ok(NixObject::Str(
+        s.strip_prefix(&prefix).map(str::to_owned).unwrap_or(s),
+    ))
+}
+
+fn remove_suffix(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let suffix = args[0].clone().try_into_string()?;
+    let s = args[1].clone().try_into_string()?;
+    Ok(NixObject::Str(
+        s.strip_suffix(&suffix).map(str::to_owned).unwrap_or(s),
+    ))
+}
+
+// Like nixpkgs, only ASCII letters are converted
+fn to_upper(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(
+        args[0].clone().try_into_string()?.to_ascii_uppercase(),
+    ))
+}
+
+fn to_lower(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(
+        args[0].clone().try_into_string()?.to_ascii_lowercase(),
+    ))
+}
+
+// Nix strings are bytes, so Nix splits multibyte characters into their bytes. Strings are
+// UTF-8 here and can't hold a lone byte, so those characters are kept whole instead
+fn string_to_characters(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let s = args[0].clone().try_into_string()?;
+    Ok(NixObject::List(Rc::new(
+        s.chars().map(|c| NixObject::Str(c.to_string())).collect(),
+    )))
+}
+
+fn char_to_int(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let s = args[0].clone().try_into_string()?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(NixObject::Int(c as i64)),
+        _ => Err(EvalError::InvalidArgument(format!(
+            "charToInt expects a single character, found {}",
+            quote_string(&s)
+        ))
+        .into()),
+    }
+}
+
+// Surrounding whitespace is allowed, anything else that is not a number is an error
+fn parse_int(s: &str) -> color_eyre::Result<i64> {
+    s.trim().parse().map_err(|_| {
+        EvalError::InvalidArgument(format!("Could not convert {} to int.", quote_string(s))).into()
+    })
+}
+
+// Zero-padded numbers are rejected, since they could also be meant as octal
+fn to_int(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let s = args[0].clone().try_into_string()?;
+    let digits = s.trim().trim_start_matches('-');
+    if digits.len() > 1 && digits.starts_with('0') {
+        return Err(EvalError::InvalidArgument(format!(
+            "toInt: Ambiguity in interpretation of {} between octal and zero padded integer.",
+            quote_string(&s)
+        ))
+        .into());
+    }
+    Ok(NixObject::Int(parse_int(&s)?))
+}
+
+fn to_int_base10(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Int(parse_int(
+        &args[0].clone().try_into_string()?,
+    )?))
+}
+
+// A Nix string literal, escaping `$` so nothing in it is interpolated
+fn escape_nix_string(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let s = args[0].clone().try_into_string()?;
+    let json = serde_json::Value::from(s).to_string();
+    Ok(NixObject::Str(json.replace('$', "\\$")))
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn escape_shell_arg(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(shell_quote(&coerce_to_string(
+        args[0].clone(),
+    )?)))
+}
+
+fn escape_shell_args(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut quoted = Vec::new();
+    for arg in args[0].clone().try_into_list()?.iter() {
+        quoted.push(shell_quote(&coerce_to_string(arg.clone())?));
+    }
+    Ok(NixObject::Str(quoted.join(" ")))
+}
+
+// Compares every pair with nix_eq, so this is O(n^2); fine for the short
+// lists found in packages and modules
+fn unique(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut out: Vec<NixObject> = Vec::new();
+    for item in args[0].clone().try_into_list()?.iter() {
+        let mut seen = false;
+        for prev in &out {
+            if nix_eq(prev, item)? {
+                seen = true;
+                break;
+            }
+        }
+        if !seen {
+            out.push(item.clone());
+        }
+    }
+    Ok(NixObject::List(Rc::new(out)))
+}
+
+// Elements of the second list that are not in the first, also O(n * m)
+fn subtract_lists(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let remove = args[0].clone().try_into_list()?;
+    let mut out = Vec::new();
+    for item in args[1].clone().try_into_list()?.iter() {
+        let mut found = false;
+        for r in remove.iter() {
+            if nix_eq(r, item)? {
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            out.push(item.clone());
+        }
+    }
+    Ok(NixObject::List(Rc::new(out)))
+}
+
+// Like builtins.functionArgs, but functors can declare their arguments in `__functionArgs`
+fn lib_function_args(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    if let NixObject::Set(NixSet::Dyn(attrs)) = args[0].force()? {
+        if let Some(formals) = attrs.get("__functionArgs") {
+            return formals.force();
+        }
+    }
+    function_args(args)
+}
+
+// Like lib.generators.mkValueStringDefault, strings are not quoted and packages become
+// their store paths
+fn value_string(val: &NixObject) -> color_eyre::Result<String> {
+    Ok(match val.force()? {
+        NixObject::Bool(b) => b.to_string(),
+        NixObject::Null => "null".to_owned(),
+        NixObject::Int(i) => i.to_string(),
+        NixObject::Float(f) => float_to_string(f),
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => s,
+        v @ NixObject::Nixpkg(_) => coerce_to_string(v)?,
+        NixObject::Set(NixSet::Dyn(attrs)) if attrs.contains_key("outPath") => {
+            coerce_to_string(attrs["outPath"].clone())?
+        }
+        v => {
+            return type_error(format!(
+                "cannot convert {} to a value string",
+                v.type_name()
+            ))
+        }
+    })
+}
+
+fn mk_value_string_default(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    expect_attrs(&args[0], "mkValueStringDefault")?;
+    Ok(NixObject::Str(value_string(&args[1])?))
+}
+
+// Like lib.generators.mkKeyValueDefault, the separator is escaped in the key
+fn key_value(
+    mk_value_string: Option<&NixObject>,
+    sep: &str,
+    key: &str,
+    val: &NixObject,
+) -> color_eyre::Result<String> {
+    let val = match mk_value_string {
+        Some(f) => f.clone().apply(val.clone())?.try_into_string()?,
+        None => value_string(val)?,
+    };
+    Ok(format!(
+        "{}{}{}",
+        key.replace(sep, &format!("\\{}", sep)),
+        sep,
+        val
+    ))
+}
+
+fn mk_key_value_default(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let opts = expect_attrs(&args[0], "mkKeyValueDefault")?;
+    let sep = args[1].clone().try_into_string()?;
+    let key = args[2].clone().try_into_string()?;
+    Ok(NixObject::Str(key_value(
+        opts.get("mkValueString"),
+        &sep,
+        &key,
+        &args[3],
+    )?))
+}
+
+// The options the key-value and INI generators take, with the defaults of nixpkgs
+#[derive(Default)]
+struct GeneratorOptions {
+    mk_key_value: Option<NixObject>,
+    mk_section_name: Option<NixObject>,
+    lists_as_duplicate_keys: bool,
+    indent: String,
+}
+
+impl GeneratorOptions {
+    fn from_attrs(val: &NixObject, fn_name: &str) -> color_eyre::Result<Self> {
+        let attrs = expect_attrs(val, fn_name)?;
+        Ok(Self {
+            mk_key_value: attrs.get("mkKeyValue").cloned(),
+            mk_section_name: attrs.get("mkSectionName").cloned(),
+            lists_as_duplicate_keys: match attrs.get("listsAsDuplicateKeys") {
+                Some(b) => b.clone().try_into_bool()?,
+                None => false,
+            },
+            indent: match attrs.get("indent") {
+                Some(indent) => indent.clone().try_into_string()?,
+                None => String::new(),
+            },
+        })
+    }
+
+    fn key_values(&self, attrs: &NixObject) -> color_eyre::Result<String> {
+        let mut out = String::new();
+        for (k, v) in expect_attrs(attrs, "toKeyValue")?.iter() {
+            let values = match v.force()? {
+                NixObject::List(items) if self.lists_as_duplicate_keys => items.to_vec(),
+                v => vec![v],
+            };
+            for v in values {
+                let line = match &self.mk_key_value {
+                    Some(f) => f
+                        .clone()
+                        .apply(NixObject::Str(k.clone()))?
+                        .apply(v)?
+                        .try_into_string()?,
+                    None => key_value(None, "=", k, &v)?,
+                };
+                out.push_str(&format!("{}{}\n", self.indent, line));
+            }
+        }
+        Ok(out)
+    }
+
+    // Sections are separated by blank lines
+    fn ini(&self, sections: &NixObject) -> color_eyre::Result<String> {
+        let mut out = Vec::new();
+        for (name, section) in expect_attrs(sections, "toINI")?.iter() {
+            let name = match &self.mk_section_name {
+                Some(f) => f
+                    .clone()
+                    .apply(NixObject::Str(name.clone()))?
+                    .try_into_string()?,
+                None => name.replace('[', "\\[").replace(']', "\\]"),
+            };
+            out.push(format!("[{}]\n{}", name, self.key_values(section)?));
+        }
+        Ok(out.join("\n"))
+    }
+}
+
+fn to_key_value(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let opts = GeneratorOptions::from_attrs(&args[0], "toKeyValue")?;
+    Ok(NixObject::Str(opts.key_values(&args[1])?))
+}
+
+fn to_ini(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let opts = GeneratorOptions::from_attrs(&args[0], "toINI")?;
+    Ok(NixObject::Str(opts.ini(&args[1])?))
+}
+
+// The global section comes first, without a header
+fn to_ini_with_global_section(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let opts = GeneratorOptions::from_attrs(&args[0], "toINIWithGlobalSection")?;
+    let attrs = expect_attrs(&args[1], "toINIWithGlobalSection")?;
+    let mut out = match attrs.get("globalSection") {
+        Some(global) if !expect_attrs(global, "toINIWithGlobalSection")?.is_empty() => {
+            opts.key_values(global)? + "\n"
+        }
+        _ => String::new(),
+    };
+    if let Some(sections) = attrs.get("sections") {
+        out.push_str(&opts.ini(sections)?);
+    }
+    Ok(NixObject::Str(out))
+}
+
+// Nested sets become subsections, so `remote.origin.url` is written as `url` under
+// `[remote "origin"]`. Packages are values rather than sections
+fn git_sections(
+    sections: &mut BTreeMap<String, BTreeMap<String, NixObject>>,
+    path: &mut Vec<String>,
+    val: &NixObject,
+) -> color_eyre::Result<()> {
+    match val.force()? {
+        NixObject::Set(NixSet::Dyn(attrs)) if !attrs.contains_key("outPath") => {
+            for (k, v) in attrs.iter() {
+                path.push(k.clone());
+                git_sections(sections, path, v)?;
+                path.pop();
+            }
+        }
+        val => match path.split_last() {
+            Some((key, section)) if !section.is_empty() => {
+                sections
+                    .entry(section.join("."))
+                    .or_default()
+                    .insert(key.clone(), val);
+            }
+            _ => {
+                return type_error(format!(
+                    "cannot write {} outside of a section in toGitINI",
+                    val.type_name()
+                ))
+            }
+        },
+    }
+    Ok(())
+}
+
+// Strings are quoted and escaped, each element of a list is written as its own line
+fn git_key_value(key: &str, val: &NixObject) -> color_eyre::Result<String> {
+    let values = match val.force()? {
+        NixObject::List(items) => items.to_vec(),
+        val => vec![val],
+    };
+    let mut lines = Vec::new();
+    for val in values {
+        let val = match val.force()? {
+            NixObject::Str(s) | NixObject::DiscardedStr(s, _) => {
+                let escaped = s
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\t', "\\t")
+                    .replace('\n', "\\n");
+                NixObject::Str(format!("\"{}\"", escaped))
+            }
+            val => val,
+        };
+        lines.push(format!("\t{}", key_value(None, " = ", key, &val)?));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn to_git_ini(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut sections = BTreeMap::new();
+    git_sections(&mut sections, &mut Vec::new(), &args[0])?;
+    let mut out = Vec::new();
+    for (name, attrs) in sections {
+        let name = match name.split_once('.') {
+            Some((section, subsection)) if !name.contains('"') => {
+                format!("{} \"{}\"", section, subsection)
+            }
+            _ => name,
+        };
+        let mut section = format!("[{}]\n", name);
+        for (k, v) in attrs.iter() {
+            section.push_str(&git_key_value(k, v)?);
+            section.push('\n');
+        }
+        out.push(section);
+    }
+    Ok(NixObject::Str(out.join("\n")))
+}
+
+// JSON is valid YAML, so like nixpkgs toYAML is toJSON
+fn generators_to_json(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    expect_attrs(&args[0], "toJSON")?;
+    to_json(&args[1..])
+}
+
+fn toml_key(k: &str) -> String {
+    let bare = k
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare && !k.is_empty() {
+        k.to_owned()
+    } else {
+        serde_json::Value::from(k).to_string()
+    }
+}
+
+fn toml_value(val: &NixObject) -> color_eyre::Result<String> {
+    Ok(match val.force()? {
+        NixObject::Bool(b) => b.to_string(),
+        NixObject::Int(i) => i.to_string(),
+        // Debug formatting keeps the decimal point that TOML floats need
+        NixObject::Float(f) if f.is_finite() => format!("{:?}", f),
+        // JSON string escapes are valid in TOML basic strings
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => serde_json::Value::from(s).to_string(),
+        NixObject::List(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(toml_value)
+                .collect::<color_eyre::Result<Vec<_>>>()?
+                .join(", ")
+        ),
+        NixObject::Set(set) => match serialized_attrs(set, "TOML")? {
+            attrs if attrs.contains_key("outPath") => {
+                toml_value(&NixObject::Str(coerce_to_string(attrs["outPath"].clone())?))?
+            }
+            attrs => format!(
+                "{{ {} }}",
+                attrs
+                    .iter()
+                    .map(|(k, v)| Ok(format!("{} = {}", toml_key(k), toml_value(v)?)))
+                    .collect::<color_eyre::Result<Vec<_>>>()?
+                    .join(", ")
+            ),
+        },
+        v => return type_error(format!("cannot convert {} to TOML", v.type_name())),
+    })
+}
+
+// Nested sets become tables, written after the keys of the table containing them
+fn toml_table(
+    out: &mut String,
+    path: &[String],
+    attrs: &BTreeMap<String, NixObject>,
+) -> color_eyre::Result<()> {
+    let mut tables = Vec::new();
+    for (k, v) in attrs {
+        match v.force()? {
+            NixObject::Set(set) => match serialized_attrs(set, "TOML")? {
+                attrs if !attrs.contains_key("outPath") => tables.push((k, attrs)),
+                attrs => out.push_str(&format!(
+                    "{} = {}\n",
+                    toml_key(k),
+                    toml_value(&NixObject::Set(NixSet::Dyn(attrs)))?
+                )),
+            },
+            v => out.push_str(&format!("{} = {}\n", toml_key(k), toml_value(&v)?)),
+        }
+    }
+    for (k, attrs) in tables {
+        let path = [path, &[toml_key(k)]].concat();
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{}]\n", path.join(".")));
+        toml_table(out, &path, &attrs)?;
+    }
+    Ok(())
+}
+
+// The file is represented by its name and contents. Sets are ordered by name, so the
+// output does not depend on the order attributes were defined in
+fn generate(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let format_type = args[0].clone().try_into_string()?;
+    let text = match format_type.as_str() {
+        // nixpkgs pretty-prints with jq, which also indents by two spaces
+        "json" => serde_json::to_string_pretty(&nix_to_json(&args[2])?)? + "\n",
+        "keyValue" => GeneratorOptions::default().key_values(&args[2])?,
+        "toml" => {
+            let mut out = String::new();
+            let attrs = serialized_attrs(args[2].clone().try_into_set()?, "TOML")?;
+            toml_table(&mut out, &[], &attrs)?;
+            out
+        }
+        "ini" => GeneratorOptions::default().ini(&args[2])?,
+        _ => {
+            return Err(EvalError::InvalidArgument(format!(
+                "generate is not supported for the {} format",
+                format_type
+            ))
+            .into())
+        }
+    };
+    Ok(NixObject::from_attrs([
+        ("name", args[1].clone()),
+        ("text", NixObject::Str(text)),
+    ]))
+}
+
+// The derivation is represented by its attributes. They can also be given as a function
+// of the final attributes, which is evaluated as a fixpoint
+fn mk_derivation(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = match args[0].force()? {
+        f @ (NixObject::Lambda(_) | NixObject::PrimOp(..)) => {
+            let fixpoint = Rc::new(OnceCell::<NixObject>::new());
+            let final_attrs = {
+                let fixpoint = fixpoint.clone();
+                NixObject::lazy(move || fixpoint.get().expect("fixpoint is set").force())
+            };
+            let attrs = f.apply(final_attrs)?;
+            fixpoint
+                .set(attrs.clone())
+                .expect("fixpoint is only set once");
+            attrs
+        }
+        attrs => attrs,
+    };
+    let mut attrs = (*expect_attrs(&attrs, "mkDerivation")?).clone();
+    attrs.insert("type".to_owned(), NixObject::Str("derivation".to_owned()));
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(attrs))))
+}
+
+// Like writeTextFile, the text is recorded as a file for the report, at `destination`
+// inside the output
+fn text_file(name: String, text: String, executable: bool, destination: String) -> NixObject {
+    let out_path = format!("{}{}", FILE_PLACEHOLDER, name);
+    FILES.with(|files| {
+        files.borrow_mut().push((
+            format!("{}{}", out_path, destination),
+            name.clone(),
+            text.clone(),
+            true,
+        ))
+    });
+    NixObject::from_attrs([
+        ("type", NixObject::Str("derivation".to_owned())),
+        ("name", NixObject::Str(name)),
+        ("text", NixObject::Str(text)),
+        ("executable", NixObject::Bool(executable)),
+        ("destination", NixObject::Str(destination)),
+        ("outPath", NixObject::Str(out_path)),
+    ])
+}
+
+fn write_text_file(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = expect_attrs(&args[0], "writeTextFile")?;
+    let required = |name: &str| match attrs.get(name) {
+        Some(val) => val.clone().try_into_string(),
+        None => Err(EvalError::InvalidArgument(format!(
+            "writeTextFile requires the attribute '{}'",
+            name
+        ))
+        .into()),
+    };
+    let executable = match attrs.get("executable") {
+        Some(executable) => executable.clone().try_into_bool()?,
+        None => false,
+    };
+    let destination = match attrs.get("destination") {
+        Some(destination) => destination.clone().try_into_string()?,
+        None => String::new(),
+    };
+    Ok(text_file(
+        required("name")?,
+        required("text")?,
+        executable,
+        destination,
+    ))
+}
+
+fn write_text(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let text = args[1].clone().try_into_string()?;
+    Ok(text_file(name, text, false, String::new()))
+}
+
+// The scripts run with bash from pkgs, which makes it a dependency
+fn shell_script(text: &NixObject) -> color_eyre::Result<String> {
+    Ok(format!(
+        "#!{}/bin/bash\n{}",
+        store_placeholder("bash"),
+        text.clone().try_into_string()?
+    ))
+}
+
+fn write_shell_script(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    Ok(text_file(
+        name,
+        shell_script(&args[1])?,
+        true,
+        String::new(),
+    ))
+}
+
+fn write_shell_script_bin(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let destination = format!("/bin/{}", name);
+    Ok(text_file(name, shell_script(&args[1])?, true, destination))
+}
+
+// The attributes are kept as they are, so the packages in them are dependencies
+fn built_derivation(mut attrs: BTreeMap<String, NixObject>, name: String) -> NixObject {
+    attrs.insert("type".to_owned(), NixObject::Str("derivation".to_owned()));
+    attrs.insert(
+        "outPath".to_owned(),
+        NixObject::Str(format!("{}{}", BUILT_PLACEHOLDER, name)),
+    );
+    attrs.insert("name".to_owned(), NixObject::Str(name));
+    NixObject::Set(NixSet::Dyn(Rc::new(attrs)))
+}
+
+// The environment attributes are captured along with the script as `buildCommand`
+fn run_command(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let mut attrs = (*expect_attrs(&args[1], "runCommand")?).clone();
+    attrs.insert("buildCommand".to_owned(), args[2].clone());
+    Ok(built_derivation(attrs, name))
+}
+
+// All attributes are captured, including `paths` and `postBuild`
+fn symlink_join(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let attrs = (*expect_attrs(&args[0], "symlinkJoin")?).clone();
+    let name = match attrs.get("name") {
+        Some(name) => name.clone().try_into_string()?,
+        None => {
+            return Err(EvalError::InvalidArgument(
+                "symlinkJoin requires the attribute 'name'".to_owned(),
+            )
+            .into())
+        }
+    };
+    match attrs.get("paths") {
+        Some(paths) => {
+            paths.clone().try_into_list()?;
+        }
+        None => {
+            return Err(EvalError::InvalidArgument(
+                "symlinkJoin requires the attribute 'paths'".to_owned(),
+            )
+            .into())
+        }
+    }
+    Ok(built_derivation(attrs, name))
+}
+
+fn option_type<'a>(
+    name: &str,
+    nested: impl IntoIterator<Item = (&'a str, NixObject)>,
+) -> NixObject {
+    NixObject::from_attrs([
+        ("_type", NixObject::Str("option-type".to_owned())),
+        ("name", NixObject::Str(name.to_owned())),
+        ("nestedTypes", NixObject::from_attrs(nested)),
+    ])
+}
+
+fn mk_option(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let mut attrs = (*expect_attrs(&args[0], "mkOption")?).clone();
+    attrs.insert("_type".to_owned(), NixObject::Str("option".to_owned()));
+    Ok(NixObject::Set(NixSet::Dyn(Rc::new(attrs))))
+}
+
+fn mk_enable_option(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    mk_option(&[NixObject::from_attrs([
+        ("default", NixObject::Bool(false)),
+        ("example", NixObject::Bool(true)),
+        (
+            "description",
+            NixObject::Str(format!("Whether to enable {}.", name)),
+        ),
+        ("type", lookup_type("bool")),
+    ])])
+}
+
+fn literal_expression(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::from_attrs([
+        ("_type", NixObject::Str("literalExpression".to_owned())),
+        ("text", args[0].clone()),
+    ]))
+}
+
+fn mk_if(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::from_attrs([
+        ("_type", NixObject::Str("if".to_owned())),
+        ("condition", args[0].clone()),
+        ("content", args[1].clone()),
+    ]))
+}
+
+fn mk_merge(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::from_attrs([
+        ("_type", NixObject::Str("merge".to_owned())),
+        ("contents", args[0].clone()),
+    ]))
+}
+
+// Tagged like nixpkgs does, so the priority is visible wherever the value ends up. A lower
+// number takes precedence
+fn mk_override(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let priority = match args[0].force()? {
+        NixObject::Int(priority) => priority,
+        v => {
+            return type_error(format!(
+                "mkOverride expects an int priority, found {}",
+                v.type_name()
+            ))
+        }
+    };
+    Ok(NixObject::from_attrs([
+        ("_type", NixObject::Str("override".to_owned())),
+        ("priority", NixObject::Int(priority)),
+        ("content", args[1].clone()),
+    ]))
+}
+
+fn mk_default(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    mk_override(&[NixObject::Int(1000), args[0].clone()])
+}
+
+fn mk_force(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    mk_override(&[NixObject::Int(50), args[0].clone()])
+}
+
+fn list_of(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(option_type("listOf", [("elemType", args[0].clone())]))
+}
+
+fn attrs_of(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(option_type("attrsOf", [("elemType", args[0].clone())]))
+}
+
+fn null_or(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(option_type("nullOr", [("elemType", args[0].clone())]))
+}
+
+/// The variables in scope at an expression. Like in Nix, a name is looked up in this order:
+///
+/// 1. the names bound by `let`, `rec` sets and function arguments, of which the innermost
+///    binding shadows the others, regardless of any `with` between them
+/// 2. the globals like `true`, `builtins` and `map`, which are bound lexically too, so no
+///    `with` can hide them
+/// 3. the namespaces of `with` expressions, innermost first
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    items: Rc<BTreeMap<String, NixObject>>,
+    // Outermost first
+    with_namespaces: Rc<Vec<NixSet>>,
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn lookup(&self, k: &str) -> Option<NixObject> {
+        if let Some(obj) = self.items.get(k).cloned().or_else(|| lookup_global(k)) {
+            return Some(obj);
+        }
+        self.with_namespaces
+            .iter()
+            .rev()
+            .find_map(|namespace| namespace.lookup(k))
+    }
+}
+
+fn token_type(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::Apply(_) => "apply",
+        Expr::Assert(_) => "assert",
+        Expr::Error(_) => "error",
+        Expr::IfElse(_) => "ifelse",
+        Expr::Select(_) => "select",
+        Expr::Str(_) => "str",
+        Expr::Path(_) => "path",
+        Expr::Literal(_) => "literal",
+        Expr::Lambda(_) => "lambda",
+        Expr::LegacyLet(_) => "legacylet",
+        Expr::LetIn(_) => "letin",
+        Expr::List(_) => "list",
+        Expr::BinOp(_) => "binop",
+        Expr::Paren(_) => "paren",
+        Expr::Root(_) => "root",
+        Expr::AttrSet(_) => "attrset",
+        Expr::UnaryOp(_) => "unaryop",
+        Expr::Ident(_) => "ident",
+        Expr::With(_) => "with",
+        Expr::HasAttr(_) => "hasattr",
+    }
+}
+
+// Selecting from an unknown value is unknown too
+fn select_attr(val: NixObject, name: &str) -> color_eyre::Result<NixObject> {
+    match val {
+        NixObject::Unknown(origin) => Ok(NixObject::Unknown(format!("{}.{}", origin, name))),
+        val => {
+            let set = val.try_into_set()?;
+            match set.lookup(name) {
+                Some(val) => Ok(val),
+                None if matches!(set, NixSet::Builtins) => {
+                    Err(EvalError::UnknownBuiltin(name.to_owned()).into())
+                }
+                None => Err(EvalError::MissingAttribute(name.to_owned()).into()),
+            }
+        }
+    }
+}
+
+// Insert `val` under a possibly nested attrpath, creating intermediate sets as needed
+fn insert_attrpath(
+    set: &mut BTreeMap<String, NixObject>,
+    path: &[String],
+    val: NixObject,
+) -> color_eyre::Result<()> {
+    let (name, rest) = path
+        .split_first()
+        .ok_or(EvalError::MalformedAst("empty attrpath"))?;
+    if rest.is_empty() {
+        if set.insert(name.clone(), val).is_some() {
+            return Err(EvalError::DuplicateAttribute(name.clone()).into());
+        }
+        return Ok(());
+    }
+    let NixObject::Set(NixSet::Dyn(inner)) = set
+        .entry(name.clone())
+        .or_insert_with(|| NixObject::from_attrs([]))
+    else {
+        return type_error(format!(
+            "cannot access attributes of non-set value '{}'",
+            name
+        ));
+    };
+    insert_attrpath(Rc::make_mut(inner), rest, val)
+}
+
+fn eval_attr_name(scope: &Scope, attr: Attr) -> color_eyre::Result<String> {
+    match attr {
+        Attr::Ident(ident) => Ok(ident.to_string()),
+        Attr::Str(s) => eval_object(scope, Expr::Str(s))?.try_into_string(),
+        Attr::Dynamic(d) => eval_object(
+            scope,
+            d.expr()
+                .ok_or(EvalError::MalformedAst("dynamic attr without expr"))?,
+        )?
+        .try_into_string(),
+    }
+}
+
+// Collect the entries of a set or let expression as lazy values. When `recursive`, the
+//  values are evaluated in a scope that contains the bindings themselves.
+fn eval_bindings(
+    scope: &Scope,
+    entries: impl Iterator<Item = Entry>,
+    recursive: bool,
+) -> color_eyre::Result<BTreeMap<String, NixObject>> {
+    // The recursive scope can only be built once all bindings are known, so the thunks
+    //  look it up when they are forced
+    let binding_scope = Rc::new(OnceCell::<Scope>::new());
+    let in_binding_scope = |expr: Expr| {
+        let binding_scope = binding_scope.clone();
+        NixObject::lazy_at(&expr.clone(), move || {
+            eval_object(
+                binding_scope.get().expect("binding scope is set"),
+                expr.clone(),
+            )
+        })
+    };
+    let mut vals = BTreeMap::new();
+    for entry in entries {
+        match entry {
+            Entry::AttrpathValue(attrval) => {
+                let path = attrval
+                    .attrpath()
+                    .ok_or(EvalError::MalformedAst("binding without attrpath"))?
+                    .attrs()
+                    .map(|attr| eval_attr_name(scope, attr))
+                    .collect::<color_eyre::Result<Vec<_>>>()?;
+                let val = in_binding_scope(
+                    attrval
+                        .value()
+                        .ok_or(EvalError::MalformedAst("binding without value"))?,
+                );
+                insert_attrpath(&mut vals, &path, val)?;
+            }
+            // In a rec set the source of `inherit (from)` can refer to the set's own
+            //  bindings, but a plain inherit always comes from the enclosing scope
+            Entry::Inherit(inherit) => {
+                let from = match inherit.from() {
+                    Some(from) => Some(in_binding_scope(
+                        from.expr()
+                            .ok_or(EvalError::MalformedAst("inherit from without expr"))?,
+                    )),
+                    None => None,
+                };
+                for attr in inherit.attrs() {
+                    let name = eval_attr_name(scope, attr)?;
+                    let val = match &from {
+                        Some(from) => {
+                            let (from, name) = (from.clone(), name.clone());
+                            NixObject::lazy(move || {
+                                from.clone()
+                                    .try_into_set()?
+                                    .lookup(&name)
+                                    .ok_or(EvalError::MissingAttribute(name.clone()).into())
+                            })
+                        }
+                        None => scope
+                            .lookup(&name)
+                            .ok_or(EvalError::NotInScope(name.clone()))?,
+                    };
+                    insert_attrpath(&mut vals, &[name], val)?;
+                }
+            }
+        }
+    }
+    let mut new_scope = scope.clone();
+    if recursive {
+        Rc::make_mut(&mut new_scope.items).extend(vals.clone());
+    }
+    binding_scope
+        .set(new_scope)
+        .expect("binding scope is only set once");
+    Ok(vals)
+}
+
+// Convert a value to a string as done by string interpolation
+// Packages are only known by name, so they are turned into a store path with a fixed
+// hash that the dependency pass can recognize inside strings
+const STORE_PLACEHOLDER: &str = "/nix/store/00000000000000000000000000000000-";
+
+// Sources get a hash of their own so they are not mistaken for packages
+const SOURCE_PLACEHOLDER: &str = "/nix/store/11111111111111111111111111111111-";
+
+// Nix hashes the output name into a path that the build replaces with the output's store
+// path. This is a fixed stand-in instead, which keeps the name readable
+const OUTPUT_PLACEHOLDER: &str = "/nix/store/22222222222222222222222222222222-output-";
+
+// Files written with builtins.toFile or the trivial builders, which are only recorded
+// since there is no store
+const FILE_PLACEHOLDER: &str = "/nix/store/33333333333333333333333333333333-";
+
+// Derivations built by trivial builders like runCommand, which are not packages from pkgs
+const BUILT_PLACEHOLDER: &str = "/nix/store/44444444444444444444444444444444-";
+
+fn store_placeholder(name: &str) -> String {
+    format!("{}{}", STORE_PLACEHOLDER, name)
+}
+
+// Store paths made up with `placeholder` that appear in `s`, with the names after the hash
+fn placeholder_paths<'a>(
+    s: &'a str,
+    placeholder: &'a str,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    s.match_indices(placeholder).map(move |(start, _)| {
+        let rest = &s[start + placeholder.len()..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "+-._?=".contains(c)))
+            .unwrap_or(rest.len());
+        (&s[start..start + placeholder.len() + end], &rest[..end])
+    })
+}
+
+// Names of the packages whose placeholders appear in `s`
+fn placeholder_names(s: &str) -> impl Iterator<Item = &str> {
+    placeholder_paths(s, STORE_PLACEHOLDER).map(|(_, name)| name)
+}
+
+fn coerce_to_string(val: NixObject) -> color_eyre::Result<String> {
+    match val.force()? {
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => Ok(s),
+        NixObject::Nixpkg(name) => Ok(store_placeholder(&name)),
+        NixObject::Path(path) => Ok(path),
+        NixObject::Source(source) => Ok(format!("{}{}", SOURCE_PLACEHOLDER, source.name)),
+        NixObject::Set(NixSet::Dyn(attrs)) if attrs.contains_key("__toString") => {
+            let f = attrs["__toString"].clone();
+            coerce_to_string(f.apply(NixObject::Set(NixSet::Dyn(attrs)))?)
+        }
+        NixObject::Set(NixSet::Dyn(attrs)) if attrs.contains_key("outPath") => {
+            coerce_to_string(attrs["outPath"].clone())
+        }
+        v => type_error(format!("cannot coerce {} to a string", v.type_name())),
+    }
+}
+
+// Sources are copied to the store, so like in Nix they are strings rather than paths
+fn is_path(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Bool(matches!(
+        args[0].force()?,
+        NixObject::Path(_)
+    )))
+}
+
+fn placeholder(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let output = args[0].clone().try_into_string()?;
+    Ok(NixObject::Str(format!("{}{}", OUTPUT_PLACEHOLDER, output)))
+}
+
+// Nix strings carry a context of the store paths they refer to, which is how interpolating
+// a package into a build command makes it a dependency. Strings don't have one here, the
+// store paths we made up are the context instead, unless the string was discarded from
+// it (see NixObject::DiscardedStr). This is only an approximation: a
+// store path that is written out rather than interpolated counts as context too
+const CONTEXT_PLACEHOLDERS: [&str; 5] = [
+    STORE_PLACEHOLDER,
+    SOURCE_PLACEHOLDER,
+    OUTPUT_PLACEHOLDER,
+    FILE_PLACEHOLDER,
+    BUILT_PLACEHOLDER,
+];
+
+fn context_paths(s: &str) -> impl Iterator<Item = &str> {
+    CONTEXT_PLACEHOLDERS
+        .iter()
+        .flat_map(move |placeholder| placeholder_paths(s, placeholder).map(|(path, _)| path))
+}
+
+// A string and the store paths in it whose context was discarded, if any were
+type StringPart = (String, Option<Rc<BTreeSet<String>>>);
+
+fn string_part(val: NixObject) -> color_eyre::Result<StringPart> {
+    let val = val.force()?;
+    let discarded = match &val {
+        NixObject::DiscardedStr(_, discarded) => Some(discarded.clone()),
+        _ => None,
+    };
+    Ok((coerce_to_string(val)?, discarded))
+}
+
+fn string_with_discarded(s: String, discarded: BTreeSet<String>) -> NixObject {
+    if discarded.is_empty() {
+        NixObject::Str(s)
+    } else {
+        NixObject::DiscardedStr(s, Rc::new(discarded))
+    }
+}
+
+// Like in Nix, the context of a concatenation is that of its parts, so a store path stays
+// discarded unless another part brings it in with its context
+fn concat_strings(parts: Vec<StringPart>) -> NixObject {
+    if parts.iter().all(|(_, discarded)| discarded.is_none()) {
+        return NixObject::Str(parts.into_iter().map(|(s, _)| s).collect());
+    }
+    let mut out = String::new();
+    let mut discarded = BTreeSet::new();
+    let mut kept = BTreeSet::new();
+    for (s, part_discarded) in parts {
+        let part_discarded = part_discarded.unwrap_or_default();
+        kept.extend(
+            context_paths(&s)
+                .filter(|path| !part_discarded.contains(*path))
+                .map(str::to_owned),
+        );
+        discarded.extend(part_discarded.iter().cloned());
+        out.push_str(&s);
+    }
+    discarded.retain(|path| !kept.contains(path));
+    string_with_discarded(out, discarded)
+}
+
+fn has_context(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (s, discarded) = args[0].clone().try_into_string_part()?;
+    let discarded = discarded.unwrap_or_default();
+    let has_context = context_paths(&s).any(|path| !discarded.contains(path));
+    Ok(NixObject::Bool(has_context))
+}
+
+thread_local! {
+    // Path, name and content of the files written since the evaluation started, and whether
+    // a derivation writes them rather than builtins.toFile
+    static FILES: RefCell<Vec<(String, String, String, bool)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+// The file is not written anywhere, its path is made up from the name and the content is
+// kept for the report
+fn to_file(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let name = args[0].clone().try_into_string()?;
+    let content = args[1].clone().try_into_string()?;
+    let path = format!("{}{}", FILE_PLACEHOLDER, name);
+    FILES.with(|files| {
+        files
+            .borrow_mut()
+            .push((path.clone(), name, content, false))
+    });
+    Ok(NixObject::Str(path))
+}
+
+// The string is left as it is, the store paths in it are only remembered as discarded
+fn discard_string_context(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    let (s, discarded) = args[0].clone().try_into_string_part()?;
+    let mut discarded = discarded.map(|d| (*d).clone()).unwrap_or_default();
+    discarded.extend(context_paths(&s).map(str::to_owned));
+    Ok(string_with_discarded(s, discarded))
+}
+
+// Like in Nix, discarding an output dependency keeps the dependency itself, and outputs
+// of .drv files aren't told apart from the paths here
+fn discard_output_dependency(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    match args[0].force()? {
+        s @ (NixObject::Str(_) | NixObject::DiscardedStr(..)) => Ok(s),
+        v => type_error(format!("expected a string, found {}", v.type_name())),
+    }
+}
+
+// builtins.toString prints floats with six decimals, like C's %f
+fn float_to_string(f: f64) -> String {
+    format!("{:.6}", f)
+}
+
+// Nix prints floats in values like C++ streams do by default, which is %g with six
+// significant digits: 1e10 is 1e+10, 1.0 is 1 and 0.1 is 0.1
+fn print_float(f: f64) -> String {
+    if f.is_nan() {
+        return "nan".to_owned();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+    if f == 0.0 {
+        return "0".to_owned();
+    }
+    let trim = |s: String| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_owned()
+        } else {
+            s
+        }
+    };
+    // Rounding to six significant digits first gives the exponent %g decides with
+    let sci = format!("{:.5e}", f);
+    let (mantissa, exp) = sci.split_once('e').expect("exponent is present");
+    let exp: i32 = exp.parse().expect("exponent is an integer");
+    if !(-4..6).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa.to_owned()), sign, exp.abs())
+    } else {
+        trim(format!("{:.*}", (5 - exp) as usize, f))
+    }
+}
+
+// builtins.toString also accepts the values that interpolation rejects
+fn to_string_value(val: &NixObject) -> color_eyre::Result<String> {
+    Ok(match val.force()? {
+        NixObject::Null | NixObject::Bool(false) => String::new(),
+        NixObject::Bool(true) => "1".to_owned(),
+        NixObject::Int(i) => i.to_string(),
+        NixObject::Float(f) => float_to_string(f),
+        NixObject::List(items) => {
+            let mut out = String::new();
+            for (i, item) in items.iter().enumerate() {
+                out += &to_string_value(item)
+                    .wrap_err_with(|| format!("while coercing list element {} to a string", i))?;
+                // Like Nix, an empty nested list doesn't get a separator after it
+                let empty_list = matches!(item.force()?, NixObject::List(l) if l.is_empty());
+                if i + 1 < items.len() && !empty_list {
+                    out.push(' ');
+                }
+            }
+            out
+        }
+        v => coerce_to_string(v)?,
+    })
+}
+
+fn to_string(args: &[NixObject]) -> color_eyre::Result<NixObject> {
+    Ok(NixObject::Str(to_string_value(&args[0])?))
+}
+
+fn nix_eq(a: &NixObject, b: &NixObject) -> color_eyre::Result<bool> {
+    Ok(match (a.force()?, b.force()?) {
+        (NixObject::Null, NixObject::Null) => true,
+        (NixObject::Bool(a), NixObject::Bool(b)) => a == b,
+        (NixObject::Int(a), NixObject::Int(b)) => a == b,
+        (NixObject::Float(a), NixObject::Float(b)) => a == b,
+        (NixObject::Int(a), NixObject::Float(b)) | (NixObject::Float(b), NixObject::Int(a)) => {
+            a as f64 == b
+        }
+        (
+            NixObject::Str(a) | NixObject::DiscardedStr(a, _),
+            NixObject::Str(b) | NixObject::DiscardedStr(b, _),
+        ) => a == b,
+        (NixObject::List(a), NixObject::List(b)) => {
+            a.len() == b.len() && {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    if !nix_eq(a, b)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+        }
+        (NixObject::Set(NixSet::Dyn(a)), NixObject::Set(NixSet::Dyn(b))) => {
+            a.len() == b.len() && {
+                for (k, a) in a.iter() {
+                    match b.get(k) {
+                        Some(b) if nix_eq(a, b)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                true
+            }
+        }
+        (NixObject::Nixpkg(a), NixObject::Nixpkg(b)) => a == b,
+        (NixObject::Path(a), NixObject::Path(b)) => canonicalize_path(&a) == canonicalize_path(&b),
+        (NixObject::Source(a), NixObject::Source(b)) => Rc::ptr_eq(&a, &b),
+        (NixObject::Set(NixSet::ConfigVal(a)), NixObject::Set(NixSet::ConfigVal(b))) => a == b,
+        _ => false,
+    })
+}
+
+fn nix_cmp(a: &NixObject, b: &NixObject) -> color_eyre::Result<Ordering> {
+    let ord = match (a.force()?, b.force()?) {
+        (NixObject::Int(a), NixObject::Int(b)) => Some(a.cmp(&b)),
+        (NixObject::Float(a), NixObject::Float(b)) => a.partial_cmp(&b),
+        (NixObject::Int(a), NixObject::Float(b)) => (a as f64).partial_cmp(&b),
+        (NixObject::Float(a), NixObject::Int(b)) => a.partial_cmp(&(b as f64)),
+        (
+            NixObject::Str(a) | NixObject::DiscardedStr(a, _),
+            NixObject::Str(b) | NixObject::DiscardedStr(b, _),
+        ) => Some(a.cmp(&b)),
+        (NixObject::List(a), NixObject::List(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                match nix_cmp(a, b)? {
+                    Ordering::Equal => {}
+                    ord => return Ok(ord),
+                }
+            }
+            Some(a.len().cmp(&b.len()))
+        }
+        (a, b) => {
+            return type_error(format!(
+                "cannot compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))
+        }
+    };
+    ord.ok_or(EvalError::TypeError("cannot compare NaN".to_owned()).into())
+}
+
+// Drop the empty and `.` components that joining paths leave behind, like Nix does when
+// appending to a path
+// Like Nix, `..` is resolved without looking at the file system
+fn canonicalize_path(path: &str) -> String {
+    let mut components = path.split('/');
+    let first = components.next().unwrap_or_default();
+    let mut rest: Vec<&str> = Vec::new();
+    for component in components {
+        match component {
+            "" | "." => {}
+            ".." if rest.last().is_some_and(|last| *last != "..") => {
+                rest.pop();
+            }
+            // The parent of the root is the root
+            ".." if first.is_empty() => {}
+            component => rest.push(component),
+        }
+    }
+    std::iter::once(first)
+        .chain(rest)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn arith(op: BinOpKind, a: NixObject, b: NixObject) -> color_eyre::Result<NixObject> {
+    match (op, a, b) {
+        (BinOpKind::Add, NixObject::Str(a), NixObject::Str(b)) => Ok(NixObject::Str(a + &b)),
+        (BinOpKind::Add, NixObject::Path(a), NixObject::Str(b) | NixObject::DiscardedStr(b, _)) => {
+            Ok(NixObject::Path(canonicalize_path(&(a + &b))))
+        }
+        (
+            BinOpKind::Add,
+            a @ (NixObject::Str(_) | NixObject::DiscardedStr(..)),
+            b @ (NixObject::Str(_)
+            | NixObject::DiscardedStr(..)
+            | NixObject::Path(_)
+            | NixObject::Source(_)),
+        ) => Ok(concat_strings(vec![string_part(a)?, string_part(b)?])),
+        (op, NixObject::Int(a), NixObject::Int(b)) => {
+            let res = match op {
+                BinOpKind::Add => a.checked_add(b),
+                BinOpKind::Sub => a.checked_sub(b),
+                BinOpKind::Mul => a.checked_mul(b),
+                _ if b == 0 => return Err(EvalError::DivisionByZero.into()),
+                _ => a.checked_div(b),
+            };
+            res.map(NixObject::Int)
+                .ok_or(EvalError::IntegerOverflow.into())
+        }
+        (
+            op,
+            a @ (NixObject::Int(_) | NixObject::Float(_)),
+            b @ (NixObject::Int(_) | NixObject::Float(_)),
+        ) => {
+            let as_float = |v| match v {
+                NixObject::Int(i) => i as f64,
+                NixObject::Float(f) => f,
+                _ => unreachable!(),
+            };
+            let (a, b) = (as_float(a), as_float(b));
+            Ok(NixObject::Float(match op {
+                BinOpKind::Add => a + b,
+                BinOpKind::Sub => a - b,
+                BinOpKind::Mul => a * b,
+                _ if b == 0.0 => return Err(EvalError::DivisionByZero.into()),
+                _ => a / b,
+            }))
+        }
+        (op, a, b) => type_error(format!(
+            "cannot apply {:?} to {} and {}",
+            op,
+            a.type_name(),
+            b.type_name()
+        )),
+    }
+}
+
+fn eval_binop(scope: &Scope, binop: rnix::ast::BinOp) -> color_eyre::Result<NixObject> {
+    let op = binop
+        .operator()
+        .ok_or(EvalError::MalformedAst("binop without operator"))?;
+    let lhs = eval_object(
+        scope,
+        binop
+            .lhs()
+            .ok_or(EvalError::MalformedAst("binop without lhs"))?,
+    )?;
+    let rhs = || {
+        eval_object(
+            scope,
+            binop
+                .rhs()
+                .ok_or(EvalError::MalformedAst("binop without rhs"))?,
+        )
+    };
+    match op {
+        BinOpKind::And => Ok(NixObject::Bool(
+            lhs.try_into_bool()? && rhs()?.try_into_bool()?,
+        )),
+        BinOpKind::Or => Ok(NixObject::Bool(
+            lhs.try_into_bool()? || rhs()?.try_into_bool()?,
+        )),
+        BinOpKind::Implication => Ok(NixObject::Bool(
+            !lhs.try_into_bool()? || rhs()?.try_into_bool()?,
+        )),
+        BinOpKind::Equal => Ok(NixObject::Bool(nix_eq(&lhs, &rhs()?)?)),
+        BinOpKind::NotEqual => Ok(NixObject::Bool(!nix_eq(&lhs, &rhs()?)?)),
+        BinOpKind::Less => Ok(NixObject::Bool(nix_cmp(&lhs, &rhs()?)?.is_lt())),
+        BinOpKind::LessOrEq => Ok(NixObject::Bool(nix_cmp(&lhs, &rhs()?)?.is_le())),
+        BinOpKind::More => Ok(NixObject::Bool(nix_cmp(&lhs, &rhs()?)?.is_gt())),
+        BinOpKind::MoreOrEq => Ok(NixObject::Bool(nix_cmp(&lhs, &rhs()?)?.is_ge())),
+        BinOpKind::Concat => {
+            let (lhs, rhs) = (lhs.try_into_list()?, rhs()?.try_into_list()?);
+            Ok(NixObject::List(Rc::new(
+                lhs.iter().chain(rhs.iter()).cloned().collect(),
+            )))
+        }
+        BinOpKind::Update => match (lhs.force()?, rhs()?.force()?) {
+            (NixObject::Unknown(origin), _) | (_, NixObject::Unknown(origin)) if lenient() => {
+                Ok(NixObject::Unknown(origin))
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (lhs.try_into_set()?, rhs.try_into_set()?);
+                let desc = format!("{} // {}", describe_set(&lhs), describe_set(&rhs));
+                match (materialize(lhs)?, materialize(rhs)?) {
+                    (Some(lhs), Some(rhs)) => {
+                        let mut merged = (*lhs).clone();
+                        merged.extend(rhs.iter().map(|(k, v)| (k.clone(), v.clone())));
+                        Ok(NixObject::Set(NixSet::Dyn(Rc::new(merged))))
+                    }
+                    _ if lenient() => Ok(NixObject::Unknown(desc)),
+                    _ => type_error(format!("cannot merge symbolic sets in {}", desc)),
+                }
+            }
+        },
+        BinOpKind::Add | BinOpKind::Sub | BinOpKind::Mul | BinOpKind::Div => arith(op, lhs, rhs()?),
+    }
+}
+
+thread_local! {
+    // Evaluations and self time per kind of expression, when profiling
+    static PROFILE: RefCell<Option<Profile>> = const { RefCell::new(None) };
+    // Whether PROFILE is set, checked on every evaluation so that it costs a single load
+    // when profiling is off
+    static PROFILING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Default)]
+struct Profile {
+    totals: HashMap<&'static str, (usize, Duration)>,
+    // Time spent in the expressions evaluated by each expression being evaluated
+    children: Vec<Duration>,
+}
+
+/// How often expressions of one kind were evaluated, and the time spent on them excluding
+/// the expressions inside them
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileEntry {
+    pub kind: &'static str,
+    pub count: usize,
+    pub time: Duration,
+}
+
+/// The time spent per kind of expression on this thread since the last call to
+/// [`eval_str`] with [`EvalOptions::profile`], including while forcing its result. The
+/// most expensive kinds come first
+pub fn profile() -> Vec<ProfileEntry> {
+    let mut entries: Vec<_> = PROFILE.with(|profile| match &*profile.borrow() {
+        Some(profile) => profile
+            .totals
+            .iter()
+            .map(|(kind, (count, time))| ProfileEntry {
+                kind,
+                count: *count,
+                time: *time,
+            })
+            .collect(),
+        None => Vec::new(),
+    });
+    entries.sort_by(|a, b| b.time.cmp(&a.time).then(a.kind.cmp(b.kind)));
+    entries
+}
+
+pub fn eval_object(scope: &Scope, expr: Expr) -> color_eyre::Result<NixObject> {
+    if !PROFILING.with(Cell::get) {
+        return eval_expr(scope, expr);
+    }
+    PROFILE.with(|profile| {
+        let mut profile = profile.borrow_mut();
+        let profile = profile.as_mut().expect("profiling is on");
+        profile.children.push(Duration::ZERO);
+    });
+    let kind = token_type(&expr);
+    let start = Instant::now();
+    let res = eval_expr(scope, expr);
+    let elapsed = start.elapsed();
+    PROFILE.with(|profile| {
+        let mut profile = profile.borrow_mut();
+        let profile = profile.as_mut().expect("profiling is on");
+        let children = profile.children.pop().expect("pushed before evaluating");
+        if let Some(parent) = profile.children.last_mut() {
+            *parent += elapsed;
+        }
+        let total = profile.totals.entry(kind).or_default();
+        total.0 += 1;
+        total.1 += elapsed.saturating_sub(children);
+    });
+    res
+}
+
+fn eval_expr(scope: &Scope, expr: Expr) -> color_eyre::Result<NixObject> {
+    match expr {
+        Expr::With(with) => {
+            let namespace = with
+                .namespace()
+                .ok_or(EvalError::MalformedAst("with has no namespace"))?;
+            let mut new_scope = scope.clone();
+            Rc::make_mut(&mut new_scope.with_namespaces)
+                .push(eval_object(scope, namespace)?.try_into_set()?);
+            eval_object(
+                &new_scope,
+                with.body()
+                    .ok_or(EvalError::MalformedAst("with has no body"))?,
+            )
+        }
+        Expr::LetIn(letin) => {
+            let mut new_scope = scope.clone();
+            let bindings = eval_bindings(scope, letin.entries(), true)?;
+            Rc::make_mut(&mut new_scope.items).extend(bindings);
+            eval_object(
+                &new_scope,
+                letin
+                    .body()
+                    .ok_or(EvalError::MalformedAst("letin without body"))?,
+            )
+        }
+        Expr::AttrSet(set) => {
+            let is_rec = set.rec_token().is_some();
+            let entries = set.entries().inspect(|entry| {
+                debug(1, || format!("evaluating binding {}", entry));
+                debug(2, || format!("{:#?}", entry));
+            });
+            let set_vals = eval_bindings(scope, entries, is_rec)?;
+            Ok(NixObject::Set(NixSet::Dyn(Rc::new(set_vals))))
+        }
+        Expr::Ident(ident) => scope
+            .lookup(ident.to_string().as_ref())
+            .ok_or(EvalError::NotInScope(ident.to_string()))?
+            .force(),
+        Expr::Select(s) => {
+            debug(1, || format!("evaluating select {}", s));
+            debug(2, || format!("{:#?}", s));
+            let initial = eval_object(
+                scope,
+                s.expr()
+                    .ok_or(EvalError::MalformedAst("select without expr"))?,
+            )?;
+            let selected = s
+                .attrpath()
+                .ok_or(EvalError::MalformedAst("select without attrpath"))?
+                .attrs()
+                .try_fold::<_, _, color_eyre::Result<_>>(Some(initial), |prev, attr| {
+                    let Some(prev) = prev else {
+                        return Ok(None);
+                    };
+                    let name = eval_attr_name(scope, attr)?;
+                    Ok(match prev {
+                        // A missing attribute is only an error without an `or` default
+                        prev if s.default_expr().is_some()
+                            && !matches!(prev, NixObject::Unknown(_)) =>
+                        {
+                            match prev.force()? {
+                                NixObject::Set(set) => set.lookup(&name),
+                                _ => None,
+                            }
+                        }
+                        prev => Some(select_attr(prev, &name)?),
+                    })
+                })?;
+            match (selected, s.default_expr()) {
+                (Some(val), _) => val.force(),
+                (None, Some(default)) => eval_object(scope, default),
+                (None, None) => unreachable!("missing attributes are errors without a default"),
+            }
+        }
+        Expr::HasAttr(has) => {
+            let mut val = eval_object(
+                scope,
+                has.expr()
+                    .ok_or(EvalError::MalformedAst("hasattr without expr"))?,
+            )?;
+            for attr in has
+                .attrpath()
+                .ok_or(EvalError::MalformedAst("hasattr without attrpath"))?
+                .attrs()
+            {
+                let name = eval_attr_name(scope, attr)?;
+                let NixObject::Set(set) = val.force()? else {
+                    return Ok(NixObject::Bool(false));
+                };
+                match set.lookup(&name) {
+                    Some(next) => val = next,
+                    None => return Ok(NixObject::Bool(false)),
+                }
+            }
+            Ok(NixObject::Bool(true))
+        }
+        Expr::Apply(a) => {
+            let lambda = eval_object(
+                scope,
+                a.lambda()
+                    .ok_or(EvalError::MalformedAst("apply without lambda"))?,
+            )?;
+            let argument = NixObject::thunk(
+                scope,
+                a.argument()
+                    .ok_or(EvalError::MalformedAst("apply without argument"))?,
+            );
+            lambda.apply(argument)
+        }
+        Expr::Literal(lit) => Ok(match lit.kind() {
+            LiteralKind::Integer(i) => NixObject::Int(i.value()?),
+            LiteralKind::Float(f) => NixObject::Float(f.value()?),
+            LiteralKind::Uri(u) => NixObject::Str(u.to_string()),
+        }),
+        Expr::Str(s) => {
+            let mut parts = Vec::new();
+            for part in s.normalized_parts() {
+                match part {
+                    InterpolPart::Literal(lit) => parts.push((lit, None)),
+                    InterpolPart::Interpolation(interpol) => parts.push(string_part(eval_object(
+                        scope,
+                        interpol
+                            .expr()
+                            .ok_or(EvalError::MalformedAst("interpolation without expr"))?,
+                    )?)?),
+                }
+            }
+            Ok(concat_strings(parts))
+        }
+        Expr::Path(p) => {
+            let mut out = String::new();
+            for part in p.parts() {
+                match part {
+                    InterpolPart::Literal(lit) => out.push_str(&lit.to_string()),
+                    InterpolPart::Interpolation(interpol) => {
+                        out.push_str(&coerce_to_string(eval_object(
+                            scope,
+                            interpol
+                                .expr()
+                                .ok_or(EvalError::MalformedAst("interpolation without expr"))?,
+                        )?)?)
+                    }
+                }
+            }
+            match out.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+                Some(search_path) => resolve_search_path(search_path),
+                None if out.starts_with(['/', '~']) => Ok(NixObject::Path(out)),
+                None => match import_dir(&Expr::Path(p)) {
+                    Some(dir) => Ok(NixObject::Path(canonicalize_path(&format!(
+                        "{}/{}",
+                        dir, out
+                    )))),
+                    None => Ok(NixObject::Path(out)),
+                },
+            }
+        }
+        Expr::List(list) => Ok(NixObject::List(Rc::new(
+            list.items().map(|e| NixObject::thunk(scope, e)).collect(),
+        ))),
+        Expr::Paren(p) => eval_object(
+            scope,
+            p.expr()
+                .ok_or(EvalError::MalformedAst("paren without expr"))?,
+        ),
+        // Lets a freshly parsed file be evaluated without taking its expression out first
+        Expr::Root(root) => eval_object(
+            scope,
+            root.expr()
+                .ok_or(EvalError::MalformedAst("root without expr"))?,
+        ),
+        // Only the branch that is taken is evaluated, so the other can refer to packages
+        // that don't exist on this platform
+        Expr::IfElse(ifelse) => {
+            let condition = eval_object(
+                scope,
+                ifelse
+                    .condition()
+                    .ok_or(EvalError::MalformedAst("if without condition"))?,
+            )?;
+            if condition.try_into_bool()? {
+                eval_object(
+                    scope,
+                    ifelse
+                        .body()
+                        .ok_or(EvalError::MalformedAst("if without body"))?,
+                )
+            } else {
+                eval_object(
+                    scope,
+                    ifelse
+                        .else_body()
+                        .ok_or(EvalError::MalformedAst("if without else"))?,
+                )
+            }
+        }
+        Expr::Assert(assert) => {
+            let condition = assert
+                .condition()
+                .ok_or(EvalError::MalformedAst("assert without condition"))?;
+            if !eval_object(scope, condition.clone())?.try_into_bool()? {
+                return Err(EvalError::AssertFailure(condition.to_string()).into());
+            }
+            eval_object(
+                scope,
+                assert
+                    .body()
+                    .ok_or(EvalError::MalformedAst("assert without body"))?,
+            )
+        }
+        Expr::UnaryOp(unary) => {
+            let val = eval_object(
+                scope,
+                unary
+                    .expr()
+                    .ok_or(EvalError::MalformedAst("unaryop without expr"))?,
+            )?;
+            match unary
+                .operator()
+                .ok_or(EvalError::MalformedAst("unaryop without operator"))?
+            {
+                UnaryOpKind::Invert => Ok(NixObject::Bool(!val.try_into_bool()?)),
+                UnaryOpKind::Negate => arith(BinOpKind::Sub, NixObject::Int(0), val),
+            }
+        }
+        Expr::BinOp(binop) => eval_binop(scope, binop),
+        Expr::Lambda(lambda) => Ok(NixObject::Lambda(Lambda {
+            scope: scope.clone(),
+            lambda,
+        })),
+        expr => Err(EvalError::UnsupportedExpr(token_type(&expr)).into()),
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+}
+
+fn quote_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn describe_set(set: &NixSet) -> String {
+    match set {
+        NixSet::Dyn(_) => "set".to_owned(),
+        NixSet::Builtins => "builtins".to_owned(),
+        NixSet::Lib => "lib".to_owned(),
+        NixSet::LibTypes => "lib.types".to_owned(),
+        NixSet::LibGenerators => "lib.generators".to_owned(),
+        NixSet::Licenses => "lib.licenses".to_owned(),
+        NixSet::Platforms => "lib.platforms".to_owned(),
+        NixSet::LibMaintainers => "lib.maintainers".to_owned(),
+        NixSet::Nixpkgs | NixSet::PkgsBefore(_) => "pkgs".to_owned(),
+        NixSet::Config => "config".to_owned(),
+        NixSet::ConfigVal(path) => format!("config.{}", path.join(".")),
+        NixSet::PkgsFormats => "pkgs.formats".to_owned(),
+        NixSet::Stdenv => "pkgs.stdenv".to_owned(),
+        NixSet::Platform => format!("{} platform", current_system()),
+        NixSet::Format { format_type } => format!("pkgs.formats.{} format", format_type),
+    }
+}
+
+fn collect_dependencies_into(
+    val: &NixObject,
+    seen: &mut HashSet<*const BTreeMap<String, NixObject>>,
+    deps: &mut BTreeSet<String>,
+) -> color_eyre::Result<()> {
+    match val.force()? {
+        NixObject::Nixpkg(name) => {
+            deps.insert(name);
+        }
+        NixObject::Set(NixSet::Stdenv) => {
+            deps.insert("stdenv".to_owned());
+        }
+        // Packages that were interpolated into strings
+        NixObject::Str(s) => deps.extend(placeholder_names(&s).map(str::to_owned)),
+        NixObject::DiscardedStr(s, discarded) => deps.extend(
+            placeholder_paths(&s, STORE_PLACEHOLDER)
+                .filter(|(path, _)| !discarded.contains(*path))
+                .map(|(_, name)| name.to_owned()),
+        ),
+        NixObject::List(items) => {
+            for item in items.iter() {
+                collect_dependencies_into(item, seen, deps)?;
+            }
+        }
+        NixObject::Set(NixSet::Dyn(attrs)) => {
+            let overlay_package = PKGS.with(|pkgs| {
+                let pkgs = pkgs.borrow();
+                pkgs.overlay_packages.get(&Rc::as_ptr(&attrs)).cloned()
+            });
+            if let Some(name) = overlay_package {
+                deps.insert(name);
+            // Sets can refer to themselves, so each is only visited once
+            } else if seen.insert(Rc::as_ptr(&attrs)) {
+                for val in attrs.values() {
+                    collect_dependencies_into(val, seen, deps)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Select an attribute path like `meta.license` from a value, like `nix eval .#meta.license`
+/// does. Quoted names such as `"a.b"` can contain dots
+pub fn select_attr_path(val: &NixObject, path: &str) -> color_eyre::Result<NixObject> {
+    let mut names = vec![String::new()];
+    let mut quoted = false;
+    for c in path.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => names.push(String::new()),
+            c => names.last_mut().expect("there is always a name").push(c),
+        }
+    }
+    if quoted || names.iter().any(String::is_empty) {
+        return Err(
+            EvalError::InvalidArgument(format!("invalid attribute path '{}'", path)).into(),
+        );
+    }
+    let mut val = val.clone();
+    for name in names {
+        val = select_attr(val, &name)
+            .wrap_err_with(|| format!("while selecting the attribute path '{}'", path))?;
+    }
+    val.force()
+}
+
+/// Names of all the packages a value refers to, forcing it completely except for the
+/// bodies of functions
+pub fn collect_dependencies(val: &NixObject) -> color_eyre::Result<BTreeSet<String>> {
+    let mut deps = BTreeSet::new();
+    collect_dependencies_into(val, &mut HashSet::new(), &mut deps)?;
+    Ok(deps)
+}
+
+fn collect_unknowns_into(
+    val: &NixObject,
+    origin: Option<Provenance>,
+    seen: &mut HashSet<*const BTreeMap<String, NixObject>>,
+    unknowns: &mut Vec<(String, Option<Provenance>)>,
+) -> color_eyre::Result<()> {
+    let origin = val.provenance().or(origin);
+    match val.force()? {
+        NixObject::Unknown(name) => unknowns.push((name, origin)),
+        NixObject::List(items) => {
+            for item in items.iter() {
+                collect_unknowns_into(item, origin, seen, unknowns)?;
+            }
+        }
+        NixObject::Set(NixSet::Dyn(attrs)) if seen.insert(Rc::as_ptr(&attrs)) => {
+            for val in attrs.values() {
+                collect_unknowns_into(val, origin, seen, unknowns)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Whether a package can be built for the system it is evaluated for, according to its
+/// `meta.broken`, `meta.platforms` and `meta.badPlatforms`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Availability {
+    pub broken: bool,
+    pub supported: bool,
+}
+
+// Platforms given as patterns rather than systems, or not known at all, are assumed to
+// match
+fn platforms_contain(platforms: &NixObject, system: &str) -> color_eyre::Result<bool> {
+    if let NixObject::Unknown(_) = platforms.force()? {
+        return Ok(true);
+    }
+    for platform in platforms.clone().try_into_list()?.iter() {
+        match platform.force()? {
+            NixObject::Str(s) | NixObject::DiscardedStr(s, _) if s != system => {}
+            _ => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+// The `meta` of a derivation, which is empty if it has none, or None for other values
+fn derivation_meta(val: &NixObject) -> color_eyre::Result<Option<Rc<BTreeMap<String, NixObject>>>> {
+    let NixObject::Set(NixSet::Dyn(attrs)) = val.force()? else {
+        return Ok(None);
+    };
+    match attrs.get("type").map(NixObject::force).transpose()? {
+        Some(NixObject::Str(t) | NixObject::DiscardedStr(t, _)) if t == "derivation" => {}
+        _ => return Ok(None),
+    }
+    Ok(Some(
+        match attrs.get("meta").map(NixObject::force).transpose()? {
+            Some(NixObject::Set(NixSet::Dyn(meta))) => meta,
+            _ => Rc::default(),
+        },
+    ))
+}
+
+/// The availability of a derivation, or None for other values
+pub fn availability(val: &NixObject) -> color_eyre::Result<Option<Availability>> {
+    let Some(meta) = derivation_meta(val)? else {
+        return Ok(None);
+    };
+    let system = current_system();
+    // Unknown values count as the defaults
+    let broken = match meta.get("broken").map(NixObject::force).transpose()? {
+        Some(NixObject::Bool(broken)) => broken,
+        _ => false,
+    };
+    let supported = match meta.get("platforms") {
+        Some(platforms) => platforms_contain(platforms, &system)?,
+        None => true,
+    } && match meta.get("badPlatforms") {
+        Some(platforms) => !platforms_contain(platforms, &system)?,
+        None => true,
+    };
+    Ok(Some(Availability { broken, supported }))
+}
+
+// Licenses from lib.licenses become their SPDX ids, or their names if they have none
+fn license_json(val: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    Ok(match val.force()? {
+        NixObject::List(licenses) => licenses
+            .iter()
+            .map(license_json)
+            .collect::<color_eyre::Result<_>>()?,
+        NixObject::Set(NixSet::Dyn(attrs)) => {
+            match ["spdxId", "shortName", "fullName"]
+                .iter()
+                .find_map(|key| attrs.get(*key))
+            {
+                Some(id) => id.clone().try_into_string()?.into(),
+                None => serde_json::Value::Null,
+            }
+        }
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => s.into(),
+        _ => serde_json::Value::Null,
+    })
+}
+
+// Maintainers from lib.maintainers are reported by their handle
+fn maintainer_json(val: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    Ok(match val.force()? {
+        NixObject::Set(NixSet::Dyn(attrs)) => match attrs.get("github") {
+            Some(handle) => handle.clone().try_into_string()?.into(),
+            None => serde_json::Value::Null,
+        },
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => s.into(),
+        _ => serde_json::Value::Null,
+    })
+}
+
+// The metadata of a derivation, with the fields it doesn't have as null
+fn meta_json(val: &NixObject) -> color_eyre::Result<Option<serde_json::Value>> {
+    let Some(meta) = derivation_meta(val)? else {
+        return Ok(None);
+    };
+    let string = |key: &str| -> color_eyre::Result<serde_json::Value> {
+        Ok(match meta.get(key).map(NixObject::force).transpose()? {
+            Some(NixObject::Str(s) | NixObject::DiscardedStr(s, _)) => s.into(),
+            _ => serde_json::Value::Null,
+        })
+    };
+    let maintainers = match meta.get("maintainers").map(NixObject::force).transpose()? {
+        Some(NixObject::List(maintainers)) => maintainers
+            .iter()
+            .map(maintainer_json)
+            .collect::<color_eyre::Result<_>>()?,
+        _ => serde_json::Value::Null,
+    };
+    Ok(Some(serde_json::json!({
+        "description": string("description")?,
+        "homepage": string("homepage")?,
+        "license": match meta.get("license") {
+            Some(license) => license_json(license)?,
+            None => serde_json::Value::Null,
+        },
+        "maintainers": maintainers,
+    })))
+}
+
+fn report_entry(key: &str, val: String, origin: Option<Provenance>) -> serde_json::Value {
+    let mut entry = serde_json::Map::new();
+    entry.insert(key.to_owned(), serde_json::Value::String(val));
+    if let Some(origin) = origin {
+        entry.insert("line".to_owned(), origin.line.into());
+        entry.insert("column".to_owned(), origin.column.into());
+    }
+    serde_json::Value::Object(entry)
+}
+
+/// Everything the evaluation learned about a value, as one JSON document: the config
+/// options read while forcing it completely, the packages it depends on, the warnings
+/// and unknown values encountered, the files made with builtins.toFile or builders like
+/// pkgs.writeText, and the [`availability`] and metadata of a package. Entries have a line and column
+/// when provenance is tracked
+pub fn report(val: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    let mut unknowns = Vec::new();
+    collect_unknowns_into(val, None, &mut HashSet::new(), &mut unknowns)?;
+    let dependencies = collect_dependencies(val)?;
+    // Paths that were only looked up on the way to the options below them are left out
+    let options = CONFIG.with(|config| {
+        let accessed = &config.borrow().accessed;
+        accessed
+            .iter()
+            .filter(|(path, _)| {
+                !accessed
+                    .keys()
+                    .any(|p| p.len() > path.len() && p.starts_with(path))
+            })
+            .map(|(path, origin)| report_entry("path", path.join("."), *origin))
+            .collect::<Vec<_>>()
+    });
+    let warnings = WARNINGS.with(|warnings| {
+        warnings
+            .borrow()
+            .iter()
+            .map(|(msg, origin)| report_entry("message", msg.clone(), *origin))
+            .collect::<Vec<_>>()
+    });
+    let files = FILES.with(|files| {
+        files
+            .borrow()
+            .iter()
+            .map(|(path, name, content, _)| {
+                serde_json::json!({
+                    "path": path,
+                    "name": name,
+                    "content": content,
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    let availability = availability(val)?.map(|availability| {
+        serde_json::json!({
+            "broken": availability.broken,
+            "supported": availability.supported,
+        })
+    });
+    Ok(serde_json::json!({
+        "availability": availability,
+        "meta": meta_json(val)?,
+        "options": options,
+        "dependencies": dependencies,
+        "warnings": warnings,
+        "files": files,
+        "unknowns": unknowns
+            .into_iter()
+            .map(|(name, origin)| report_entry("value", name, origin))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+// https://json-schema.org/understanding-json-schema/reference/type
+fn type_schema(ty: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    use serde_json::json;
+    let attrs = expect_attrs(ty, "option type")?;
+    let name = match attrs.get("name") {
+        Some(name) => name.clone().try_into_string()?,
+        None => return type_error("option type without a name".to_owned()),
+    };
+    let elem_type = || -> color_eyre::Result<serde_json::Value> {
+        let nested = expect_attrs(&attrs["nestedTypes"], "nestedTypes")?;
+        match nested.get("elemType") {
+            Some(elem) => type_schema(elem),
+            None => type_error(format!("{} without an elemType", name)),
+        }
+    };
+    Ok(match name.as_str() {
+        "str" | "string" | "lines" | "commas" | "envVar" | "singleLineStr" | "nonEmptyStr"
+        | "path" | "package" => json!({ "type": "string" }),
+        "int" => json!({ "type": "integer" }),
+        "port" => json!({ "type": "integer", "minimum": 0, "maximum": 65535 }),
+        "float" | "number" => json!({ "type": "number" }),
+        "bool" => json!({ "type": "boolean" }),
+        "attrs" => json!({ "type": "object" }),
+        "anything" | "unspecified" | "raw" => json!({}),
+        "listOf" => json!({ "type": "array", "items": elem_type()? }),
+        "attrsOf" => json!({ "type": "object", "additionalProperties": elem_type()? }),
+        "nullOr" => json!({ "anyOf": [elem_type()?, { "type": "null" }] }),
+        // The type of a pkgs.formats backend accepts whatever the format can represent
+        name if name.ends_with(" value") => json!({}),
+        name => {
+            warn(&format!(
+                "option type {} has no JSON Schema equivalent, allowing any value",
+                name
+            ));
+            json!({})
+        }
+    })
+}
+
+fn options_schema(options: &BTreeMap<String, NixObject>) -> color_eyre::Result<serde_json::Value> {
+    let mut properties = serde_json::Map::new();
+    for (name, val) in options.iter() {
+        let NixObject::Set(NixSet::Dyn(attrs)) = val.force()? else {
+            continue;
+        };
+        let schema = if has_type(&attrs, "option")? {
+            let mut schema = match attrs.get("type") {
+                Some(ty) => type_schema(ty)
+                    .wrap_err_with(|| format!("while describing the type of option {}", name))?,
+                None => serde_json::json!({}),
+            };
+            if let Some(NixObject::Str(description) | NixObject::DiscardedStr(description, _)) =
+                attrs.get("description").map(|d| d.force()).transpose()?
+            {
+                schema["description"] = serde_json::Value::String(description);
+            }
+            // Defaults that can't be represented, like packages, are left out
+            if let Some(default) = attrs.get("default").and_then(|d| nix_to_json(d).ok()) {
+                schema["default"] = default;
+            }
+            schema
+        } else {
+            options_schema(&attrs)?
+        };
+        properties.insert(name.clone(), schema);
+    }
+    Ok(serde_json::json!({ "type": "object", "properties": properties }))
+}
+
+/// A JSON Schema for the values accepted by the options declared with `mkOption`, like the
+/// `settings` of a module. `val` is either a module, whose `options` are described, or a set
+/// of options itself. Types without an equivalent in JSON Schema accept any value, with a
+/// warning
+pub fn json_schema(val: &NixObject) -> color_eyre::Result<serde_json::Value> {
+    let attrs = expect_attrs(val, "json_schema")?;
+    // A set of options can declare one named `options` too
+    let module_options = match attrs.get("options") {
+        Some(options) => Some(expect_attrs(options, "options")?),
+        None => None,
+    };
+    let mut schema = match module_options {
+        Some(options) if !has_type(&options, "option")? => options_schema(&options)?,
+        _ => options_schema(&attrs)?,
+    };
+    schema["$schema"] =
+        serde_json::Value::String("https://json-schema.org/draft/2020-12/schema".to_owned());
+    Ok(schema)
+}
+
+// The attributes of a derivation that list its inputs, with the label of their edges
+const INPUT_CATEGORIES: [(&str, &str); 4] = [
+    ("buildInputs", "build"),
+    ("nativeBuildInputs", "native"),
+    ("propagatedBuildInputs", "propagated"),
+    ("propagatedNativeBuildInputs", "propagated"),
+];
+
+fn unresolved_inputs_into(
+    val: &NixObject,
+    origin: Option<Provenance>,
+    unresolved: &mut Vec<(String, Option<Provenance>)>,
+) -> color_eyre::Result<()> {
+    let origin = val.provenance().or(origin);
+    match val.force()? {
+        NixObject::Unknown(name) => unresolved.push((name, origin)),
+        NixObject::List(items) => {
+            for item in items.iter() {
+                unresolved_inputs_into(item, origin, unresolved)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The inputs of a package that are unknown values rather than packages, with where they
+/// were defined when provenance is tracked. These are the input lists of a derivation and
+/// the `paths` of symlinkJoin
+pub fn unresolved_dependencies(
+    val: &NixObject,
+) -> color_eyre::Result<Vec<(String, Option<Provenance>)>> {
+    let mut unresolved = Vec::new();
+    if let NixObject::Set(NixSet::Dyn(attrs)) = val.force()? {
+        let inputs = INPUT_CATEGORIES.iter().map(|(attr, _)| *attr);
+        for attr in inputs.chain(["paths"]) {
+            if let Some(inputs) = attrs.get(attr) {
+                unresolved_inputs_into(inputs, None, &mut unresolved)?;
+            }
+        }
+    }
+    Ok(unresolved)
+}
+
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Render the dependencies of a package as a GraphViz graph, with an edge from the
+/// package to each of them labelled by the kind of input it is. Packages referred to
+/// outside of the input lists are labelled "other"
+pub fn dependency_graph(val: &NixObject) -> color_eyre::Result<String> {
+    let mut edges = BTreeSet::new();
+    let mut root = "package".to_owned();
+    let mut categorized = BTreeSet::new();
+    if let NixObject::Set(NixSet::Dyn(attrs)) = val.force()? {
+        if let Some(name) = attrs.get("pname").or(attrs.get("name")) {
+            root = coerce_to_string(name.clone())?;
+        }
+        for (attr, label) in INPUT_CATEGORIES {
+            if let Some(inputs) = attrs.get(attr) {
+                for dep in collect_dependencies(inputs)? {
+                    categorized.insert(dep.clone());
+                    edges.insert((dep, label));
+                }
+            }
+        }
+    }
+    for dep in collect_dependencies(val)? {
+        if !categorized.contains(&dep) {
+            edges.insert((dep, "other"));
+        }
+    }
+
+    let mut out = format!("digraph {} {{\n", dot_id(&root));
+    for (dep, label) in edges {
+        out.push_str(&format!(
+            "  {} -> {} [label={}];\n",
+            dot_id(&root),
+            dot_id(&dep),
+            dot_id(label)
+        ));
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+// Render a value the way `nix eval` does, forcing it completely
+pub fn print_value(val: &NixObject) -> color_eyre::Result<String> {
+    Ok(match val.force()? {
+        NixObject::Null => "null".to_owned(),
+        NixObject::Bool(b) => b.to_string(),
+        NixObject::Int(i) => i.to_string(),
+        NixObject::Float(f) => print_float(f),
+        NixObject::Str(s) | NixObject::DiscardedStr(s, _) => quote_string(&s),
+        NixObject::List(items) => {
+            let mut out = String::from("[ ");
+            for item in items.iter() {
+                out.push_str(&print_or_recover(item)?);
+                out.push(' ');
+            }
+            out.push(']');
+            out
+        }
+        NixObject::Set(NixSet::Dyn(attrs)) => {
+            let mut out = String::from("{ ");
+            for (name, val) in attrs.iter() {
+                if is_ident(name) {
+                    out.push_str(name);
+                } else {
+                    out.push_str(&quote_string(name));
+                }
+                out.push_str(" = ");
+                out.push_str(&print_or_recover(val)?);
+                out.push_str("; ");
+            }
+            out.push('}');
+            out
+        }
+        NixObject::Set(set) => format!("«{}»", describe_set(&set)),
+        NixObject::Thunk(_) => unreachable!("forced values are not thunks"),
+        NixObject::Lambda(_) => "«lambda»".to_owned(),
+        NixObject::PrimOp(op, args) if args.is_empty() => format!("«primop {}»", op.name),
+        NixObject::PrimOp(op, _) => format!("«primop-app {}»", op.name),
+        NixObject::Nixpkg(name) => format!("«pkgs.{}»", name),
+        NixObject::Path(path) => path,
+        NixObject::Source(source) if source.filters.is_empty() => {
+            format!("«source {}»", source.path)
+        }
+        NixObject::Source(source) => {
+            let filters = source
+                .filters
+                .iter()
+                .map(|filter| match filter {
+                    SourceFilter::Custom(f) => match f.provenance() {
+                        Some(origin) => format!("the function at {}", origin),
+                        None => "a function".to_owned(),
+                    },
+                    SourceFilter::Clean => "cleanSource".to_owned(),
+                    SourceFilter::Regexes(regexes) => format!(
+                        "sourceByRegex [ {}]",
+                        regexes
+                            .iter()
+                            .map(|r| quote_string(r) + " ")
+                            .collect::<String>()
+                    ),
+                })
+                .collect::<Vec<_>>();
+            format!(
+                "«source {} filtered by {}»",
+                source.path,
+                filters.join(", ")
+            )
+        }
+        NixObject::FormatFactory { format_type } => format!("«pkgs.formats.{}»", format_type),
+        NixObject::Unknown(origin) => format!("«unknown {}»", origin),
+    })
+}
+
+/// Options controlling how a file is evaluated
+#[derive(Clone, Debug, Default)]
+pub struct EvalOptions {
+    /// Top-level arguments to declare, on top of and overriding lib, pkgs and config
+    pub args: HashMap<String, ArgKind>,
+    /// Bind top-level arguments that are neither declared nor defaulted to unknown values
+    /// instead of failing
+    pub lenient: bool,
+    /// Names of the packages in `pkgs`. When given, accessing any other package is an
+    /// error, or only a warning in lenient mode
+    pub known_packages: Option<HashSet<String>>,
+    /// The search path that `<nixpkgs>` and such are looked up in, in the format of
+    /// NIX_PATH like `nixpkgs=/src/nixpkgs:/other/dir`. Empty by default
+    pub nix_path: Option<String>,
+    /// The maintainers in lib.maintainers by their handle, like nixpkgs'
+    /// maintainer-list.nix as JSON. When given, other handles are errors, or only warnings
+    /// in lenient mode
+    pub maintainers: Option<serde_json::Value>,
+    /// Sources of overlays like `self: super: { ... }` to apply to `pkgs` in order. The
+    /// packages they define are reported as dependencies like the others
+    pub overlays: Vec<String>,
+    /// Allow builtins such as getEnv to read from the machine running the evaluation, which
+    /// makes the result depend on it
+    pub impure: bool,
+    /// Make importing the output of a derivation, which Nix would have to build first, an
+    /// unknown value with a warning rather than an error
+    pub allow_ifd: bool,
+    /// The system to evaluate for, like x86_64-linux, which platform conditionals are
+    /// resolved against. Defaults to the system running the evaluation
+    pub system: Option<String>,
+    /// Remember where values are defined, so errors can say which definitions were being
+    /// evaluated and [`NixObject::provenance`] is known. This makes evaluation slower
+    pub provenance: bool,
+    /// How many levels of evaluation diagnostics to print to stderr, as with `-v`
+    pub trace: u8,
+    /// Time the evaluation of each kind of expression, see [`profile`]
+    pub profile: bool,
+    /// What builtins.storeDir is, `/nix/store` by default. The store paths made up for
+    /// packages and files stay in `/nix/store` regardless
+    pub store_dir: Option<String>,
+    /// The Nix version to report from builtins.nixVersion, [`DEFAULT_NIX_VERSION`] by
+    /// default
+    pub nix_version: Option<String>,
+    /// Concrete values for `config`, taking precedence over the defaults of the options
+    /// declared by the module
+    pub config: Option<serde_json::Value>,
+}
+
+/// The file that evaluating `path` evaluates, which is the `default.nix` in it if it is a
+/// directory
+pub fn resolve_nix_file(path: impl AsRef<Path>) -> color_eyre::Result<std::path::PathBuf> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Ok(path.to_owned());
+    }
+    let file = path.join("default.nix");
+    if !file.is_file() {
+        return Err(EvalError::InvalidArgument(format!(
+            "directory {} does not contain a default.nix",
+            path.display()
+        ))
+        .into());
+    }
+    Ok(file)
+}
+
+/// Evaluate the file at `path`, or the `default.nix` in it if it is a directory. Unlike
+/// with [`eval_str`], relative paths in it are resolved against the directory it is in
+pub fn eval_file(path: impl AsRef<Path>, options: &EvalOptions) -> color_eyre::Result<NixObject> {
+    let file = resolve_nix_file(path)?;
+    let source = std::fs::read_to_string(&file)
+        .wrap_err_with(|| format!("while reading {}", file.display()))?;
+    let dir = dir_name(&file.to_string_lossy()).to_owned();
+    eval_source(&source, Some(dir), options)
+}
+
+/// Evaluate Nix source. If it is a lambda taking an attribute set, like a package or
+/// module, it is called with its arguments bound according to `options`, otherwise the
+/// expression is evaluated as is
+pub fn eval_str(source: &str, options: &EvalOptions) -> color_eyre::Result<NixObject> {
+    eval_source(source, None, options)
+}
+
+// Relative paths are resolved against `dir` if the source is from a file
+fn eval_source(
+    source: &str,
+    dir: Option<String>,
+    options: &EvalOptions,
+) -> color_eyre::Result<NixObject> {
+    VERBOSITY.with(|verbosity| verbosity.set(options.trace));
+    WARNINGS.with(|warnings| warnings.borrow_mut().clear());
+    ERRORS.with(|errors| errors.borrow_mut().clear());
+    FILES.with(|files| files.borrow_mut().clear());
+    PROFILE.with(|profile| *profile.borrow_mut() = options.profile.then(Profile::default));
+    PROFILING.with(|profiling| profiling.set(options.profile));
+    IMPURE.with(|impure| impure.set(options.impure));
+    ALLOW_IFD.with(|allow_ifd| allow_ifd.set(options.allow_ifd));
+    LENIENT.with(|lenient| lenient.set(options.lenient));
+    IMPORTS.with(|imports| imports.borrow_mut().clear());
+    let ast = rnix::Root::parse(source).ok()?;
+    IMPORT_DIRS.with(|dirs| {
+        *dirs.borrow_mut() = dir
+            .map(|dir| (ast.syntax().clone(), dir))
+            .into_iter()
+            .collect()
+    });
+    LINE_STARTS.with(|starts| {
+        *starts.borrow_mut() = options.provenance.then(|| {
+            let newlines = source.match_indices('\n').map(|(i, _)| i + 1);
+            let starts = std::iter::once(0).chain(newlines).collect();
+            (ast.syntax().clone(), Rc::new(starts))
+        })
+    });
+    SYSTEM.with(|system| *system.borrow_mut() = options.system.clone().unwrap_or_else(host_system));
+    STORE_DIR.with(|dir| {
+        *dir.borrow_mut() = options
+            .store_dir
+            .clone()
+            .unwrap_or_else(|| "/nix/store".to_owned())
+    });
+    NIX_VERSION.with(|version| {
+        *version.borrow_mut() = options
+            .nix_version
+            .clone()
+            .unwrap_or_else(|| DEFAULT_NIX_VERSION.to_owned())
+    });
+    CONFIG.with(|config| {
+        *config.borrow_mut() = ConfigState {
+            values: options.config.clone().map(json_to_nix),
+            ..Default::default()
+        }
+    });
+    PKGS.with(|pkgs| {
+        *pkgs.borrow_mut() = PkgsState {
+            known: options.known_packages.clone(),
+            lenient: options.lenient,
+            ..Default::default()
+        }
+    });
+    let known_maintainers = match options.maintainers.clone().map(json_to_nix) {
+        Some(NixObject::Set(NixSet::Dyn(known))) => Some(known),
+        Some(_) => {
+            return Err(EvalError::InvalidArgument(
+                "the maintainers have to be an object".to_owned(),
+            )
+            .into())
+        }
+        None => None,
+    };
+    NIX_PATH.with(|nix_path| {
+        *nix_path.borrow_mut() = options
+            .nix_path
+            .as_deref()
+            .map(parse_nix_path)
+            .unwrap_or_default()
+    });
+    MAINTAINERS.with(|maintainers| {
+        *maintainers.borrow_mut() = MaintainersState {
+            known: known_maintainers,
+            ..Default::default()
+        }
+    });
+    for (i, overlay) in options.overlays.iter().enumerate() {
+        apply_overlay(i, overlay).wrap_err_with(|| format!("while applying overlay {}", i + 1))?;
+    }
+
+    let result = match ast.expr().ok_or(eyre!("file is empty"))? {
+        Expr::Lambda(lambda) => call_root_lambda(lambda, options)?,
+        expr => {
+            declare_module_options(&Scope::new(), expr.clone());
+            eval_object(&Scope::new(), expr)?
+        }
+    };
+    // Modules that declare_module_options can't see into, like an imported one, declare
+    // their options once they are evaluated, which is still before `config` is forced by
+    // printing them
+    if let NixObject::Set(NixSet::Dyn(module)) = &result {
+        if let Some(options) = module.get("options") {
+            let options = expect_attrs(options, "options")?;
+            let mut declarations = HashMap::new();
+            collect_options(&mut Vec::new(), &options, &mut declarations)?;
+            CONFIG.with(|config| config.borrow_mut().declarations = declarations);
+        }
+    }
+    // Nix would refuse to evaluate these packages without allowing them in its config
+    if let Some(availability) = availability(&result)? {
+        if availability.broken {
+            warn("the package is marked as broken");
+        }
+        if !availability.supported {
+            warn(&format!(
+                "the package is not available on {}",
+                current_system()
+            ));
+        }
+    }
+    Ok(result)
+}
+
+// Overlays are called with the final package set as `self`, and the one that the
+// overlays before it produced as `super`
+fn apply_overlay(index: usize, source: &str) -> color_eyre::Result<()> {
+    let ast = rnix::Root::parse(source).ok()?;
+    let overlay = eval_object(&Scope::new(), ast.expr().ok_or(eyre!("overlay is empty"))?)?;
+    let attrs = overlay
+        .apply(NixObject::Set(NixSet::Nixpkgs))?
+        .apply(NixObject::Set(NixSet::PkgsBefore(index)))?;
+    let attrs = expect_attrs(&attrs, "overlay")?;
+    PKGS.with(|pkgs| pkgs.borrow_mut().overlays.push(attrs));
+    Ok(())
+}
+
+// Bind the arguments of a top-level lambda like callPackage does and evaluate its body
+fn call_root_lambda(
+    lambda: rnix::ast::Lambda,
+    options: &EvalOptions,
+) -> color_eyre::Result<NixObject> {
+    let root_args = CALLPACKAGE_ARGS
+        .entries()
+        .map(|(name, kind)| (name.to_string(), *kind))
+        .chain(
+            options
+                .args
+                .iter()
+                .map(|(name, kind)| (name.clone(), *kind)),
+        )
+        .collect::<HashMap<_, _>>();
+
+    let mut scope = Scope::new();
+    let param = lambda.param().ok_or(EvalError::MalformedAst(
+        "top-level lambda does not have a param",
+    ))?;
+    let Param::Pattern(pat) = param else {
+        return type_error("top-level lambda does not destructure its argument".to_owned());
+    };
+    let mut all_args = BTreeMap::new();
+    // Like the defaults of other lambdas, these may refer to any of the arguments
+    let param_scope = Rc::new(OnceCell::<Scope>::new());
+    for e in pat.pat_entries() {
+        let ident_node = e
+            .ident()
+            .ok_or(EvalError::MalformedAst("pat entry without ident"))?;
+        let ident = ident_node.to_string();
+        let val = match (root_args.get(&ident), e.default()) {
+            (Some(kind), _) => kind.to_object(&ident),
+            (None, Some(default)) => {
+                let param_scope = param_scope.clone();
+                NixObject::lazy(move || {
+                    eval_object(
+                        param_scope.get().expect("param scope is set"),
+                        default.clone(),
+                    )
+                })
+            }
+            (None, None) if options.lenient => NixObject::Unknown(ident.clone()),
+            (None, None) => {
+                let err = eyre::Report::from(EvalError::UnknownRootArgument(ident));
+                // Points the error at the argument, like the errors in values
+                return Err(match provenance_of(&Expr::Ident(ident_node)) {
+                    Some(origin) => err.wrap_err(format!("{}{}", DEFINED_AT, origin)),
+                    None => err,
+                });
+            }
+        };
+        all_args.insert(ident.clone(), val.clone());
+        Rc::make_mut(&mut scope.items).insert(ident, val);
+    }
+    if let Some(bind) = pat.pat_bind() {
+        Rc::make_mut(&mut scope.items).insert(
+            bind.ident()
+                .ok_or(EvalError::MalformedAst("bind without ident"))?
+                .to_string(),
+            NixObject::Set(NixSet::Dyn(Rc::new(all_args))),
+        );
+    }
+    param_scope
+        .set(scope.clone())
+        .expect("param scope is only set once");
+
+    let body = lambda
+        .body()
+        .ok_or(EvalError::MalformedAst("lambda without body"))?;
+    declare_module_options(&scope, body.clone());
+    eval_object(&scope, body)
+}
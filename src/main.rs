@@ -1,283 +1,627 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use eyre::eyre;
-use phf::phf_map;
-use rnix::ast::{Attr, Attrpath, Entry, Expr, HasEntry, Param};
-use std::collections::HashMap;
-
-#[derive(Clone, Debug)]
-enum NixObject {
-    Set(NixSet),
-    Nixpkg(String),
-    FormatFactory { format_type: String },
+use notify::event::ModifyKind;
+use notify::{EventKind, RecursiveMode, Watcher};
+use rnix::parser::ParseError;
+use rnix_experiments::{
+    collect_dependencies, dependency_graph, error_origin, errors, eval_file, host_system,
+    imported_files, json_schema, print_value, profile, report, resolve_nix_file, select_attr_path,
+    unresolved_dependencies, warning_count, ArgKind, Diagnostic, EvalError, EvalOptions, NixObject,
+    Provenance, DEFAULT_NIX_VERSION,
+};
+use rowan::ast::AstNode;
+use std::collections::{BTreeMap, HashSet};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn parse_root_arg(s: &str) -> color_eyre::Result<(String, ArgKind)> {
+    let (name, kind) = s
+        .split_once('=')
+        .ok_or(eyre!("expected NAME=KIND, found '{}'", s))?;
+    Ok((name.to_owned(), kind.parse()?))
 }
 
-impl NixObject {
-    fn try_into_set(self) -> color_eyre::Result<NixSet> {
-        match self {
-            Self::Set(s) => Ok(s),
-            Self::Nixpkg(pkg) => Err(eyre!("nixpkg '{}' cannot be treated as set", pkg)),
-            Self::FormatFactory { format_type } => Err(eyre!(
-                "'{}' format factory cannot be treated as set",
-                format_type
-            )),
-        }
-    }
+// Blank lines and lines starting with # are ignored
+fn parse_package_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
 
-    fn apply(self, arg: NixObject) -> color_eyre::Result<NixObject> {
-        Err(eyre!("cannot apply lambda of type {:#?}", self))
-    }
+/// Symbolically evaluate a nixpkgs-style package or module
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    eval: EvalArgs,
+
+    /// System to evaluate platform conditionals for, like x86_64-linux or aarch64-darwin
+    #[arg(long, default_value_t = host_system())]
+    system: String,
+
+    /// Print the dependencies of the package as a GraphViz graph instead of its value
+    #[arg(long)]
+    graph: bool,
+
+    /// Print the config options read, the dependencies, the warnings and unknown values
+    /// encountered, and the files written by builtins.toFile instead of the value.
+    /// Provenance is tracked to locate them
+    #[arg(long, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// Print a JSON Schema of the values accepted by the options the module declares with
+    /// mkOption instead of its value. Select a part of them, like its settings, with --attr
+    #[arg(long)]
+    json_schema: bool,
+
+    /// Fail if any input of the package is an unknown value rather than a package, listing
+    /// where they were defined
+    #[arg(long)]
+    strict_deps: bool,
+
+    /// Evaluate the file again whenever it or the other files given change, printing the
+    /// new result
+    #[arg(long)]
+    watch: bool,
+
+    /// Print errors to stderr as a JSON object with their kind, message and location
+    /// instead of as text. With --lenient, the errors found are printed as an array of them
+    #[arg(long, value_name = "FORMAT", default_value = "human")]
+    error_format: ErrorFormat,
+
+    /// Print the time spent evaluating each kind of expression to stderr, excluding the
+    /// expressions inside them
+    #[arg(long)]
+    profile: bool,
+
+    /// Print the syntax tree of the file before evaluating it
+    #[arg(long)]
+    dump_ast: bool,
+
+    /// Print the syntax tree of the file and exit without evaluating it
+    #[arg(long)]
+    dump_ast_only: bool,
 }
 
-#[derive(Clone, Debug)]
-enum NixSet {
-    Dyn(HashMap<String, NixObject>),
-    CallpkgArgs,
-    Lib,
-    Nixpkgs,
-    Config,
-    ConfigVal(Vec<String>),
-    PkgsFormats,
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Json,
 }
 
-static CALLPACKAGE_ARGS: phf::Map<&'static str, NixSet> = phf_map! {
-    "lib" => NixSet::Lib,
-    "pkgs" => NixSet::Nixpkgs,
-    "config" => NixSet::Config,
-};
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
 
-static LIB: phf::Map<&'static str, NixSet> = phf_map! {};
+#[derive(Subcommand)]
+enum Command {
+    /// Print the dependencies a package gains (+) and loses (-) when evaluated for the
+    /// second system instead of the first
+    DiffDeps {
+        /// The two systems to compare, like x86_64-linux and aarch64-darwin
+        #[arg(long = "system", required = true, num_args = 1)]
+        systems: Vec<String>,
 
-fn lookup_nixpkg(name: &str) -> NixObject {
-    if name == "formats" {
-        return NixObject::Set(NixSet::PkgsFormats);
-    }
-    NixObject::Nixpkg(name.to_owned())
+        #[command(flatten)]
+        eval: EvalArgs,
+    },
+    /// Evaluate the file without printing it, exiting with a non-zero status if evaluation
+    /// fails
+    Check {
+        #[command(flatten)]
+        eval: EvalArgs,
+    },
 }
 
-impl NixSet {
-    fn lookup(&self, k: &str) -> Option<NixObject> {
-        let h = |m: &phf::Map<&'static str, NixSet>| m.get(k).map(|v| NixObject::Set(v.clone()));
-        match self {
-            Self::Dyn(s) => s.get(k).cloned(),
-            Self::CallpkgArgs => h(&CALLPACKAGE_ARGS),
-            Self::Lib => h(&LIB),
-            Self::Nixpkgs => Some(lookup_nixpkg(k)),
-            Self::Config => Some(NixObject::Set(NixSet::ConfigVal(vec![k.to_owned()]))),
-            Self::ConfigVal(path) => Some(NixObject::Set(NixSet::ConfigVal(
-                path.iter()
-                    .cloned()
-                    .chain(std::iter::once(k.to_owned()))
-                    .collect(),
-            ))),
-            // https://github.com/NixOS/nixpkgs/blob/master/pkgs/pkgs-lib/formats.nix
-            Self::PkgsFormats => Some(NixObject::FormatFactory {
-                format_type: k.to_owned(),
-            }),
+#[derive(Args)]
+struct EvalArgs {
+    /// File to evaluate, or a directory to evaluate the default.nix of. A lambda taking an
+    /// attribute set is called with its arguments bound like callPackage does
+    #[arg(required = true)]
+    file: Option<PathBuf>,
+
+    /// Declare an additional top-level argument. KIND is one of lib, pkgs, config,
+    /// pkg (the package of the same name), set (an empty set) or unknown
+    #[arg(long = "arg", value_name = "NAME=KIND", value_parser = parse_root_arg)]
+    args: Vec<(String, ArgKind)>,
+
+    /// Bind top-level arguments that are neither known nor defaulted to an unknown value
+    /// instead of failing. Parts of the value that fail to evaluate are printed as
+    /// «error», and the errors are listed together afterwards
+    #[arg(long)]
+    lenient: bool,
+
+    /// Print at most this many of the errors collected with --lenient
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    max_errors: usize,
+
+    /// JSON file with concrete values for `config`, taking precedence over the defaults of
+    /// the options declared by the module
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// File listing the names of the packages in `pkgs`, one per line. Accessing any other
+    /// package is then an error, or a warning with --lenient
+    #[arg(long, value_name = "FILE")]
+    known_packages: Option<PathBuf>,
+
+    /// Attribute path to select from the result, like `meta` or `passthru.tests`. Only that
+    /// value is then printed or analyzed
+    #[arg(long = "attr", short = 'A', value_name = "ATTRPATH")]
+    attr: Option<String>,
+
+    /// Store directory that builtins.storeDir evaluates to
+    #[arg(long, value_name = "DIR", default_value = "/nix/store")]
+    store_dir: String,
+
+    /// Nix version that builtins.nixVersion evaluates to, to emulate other Nix releases
+    #[arg(long, value_name = "VERSION", default_value = DEFAULT_NIX_VERSION)]
+    nix_version: String,
+
+    /// Search path to look up `<nixpkgs>` and such in, like `nixpkgs=/src/nixpkgs`.
+    /// Defaults to the NIX_PATH environment variable
+    #[arg(long, value_name = "PATH")]
+    nix_path: Option<String>,
+
+    /// JSON file with the maintainers in lib.maintainers by their handle, like nixpkgs'
+    /// maintainer-list.nix. Accessing any other maintainer is then an error, or a warning
+    /// with --lenient
+    #[arg(long, value_name = "FILE")]
+    maintainers: Option<PathBuf>,
+
+    /// Nix file with an overlay like `self: super: { ... }` to apply to `pkgs`. Can be
+    /// given more than once, the overlays are applied in order
+    #[arg(long = "overlay", value_name = "FILE")]
+    overlays: Vec<PathBuf>,
+
+    /// Let builtins such as getEnv read from this machine, so the result depends on the
+    /// environment it was evaluated in. Otherwise their results are unknown
+    #[arg(long)]
+    impure: bool,
+
+    /// Evaluate importing the output of a derivation to an unknown value with a warning
+    /// instead of failing, since the derivation can't be built here
+    #[arg(long, visible_alias = "allow-import-from-derivation")]
+    allow_ifd: bool,
+
+    /// Track where values are defined, so errors show the definitions being evaluated
+    #[arg(long)]
+    provenance: bool,
+
+    /// Print evaluation diagnostics to stderr. Pass twice to include syntax nodes
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+impl EvalArgs {
+    fn file(&self) -> &PathBuf {
+        self.file.as_ref().expect("file is required")
+    }
+
+    // Evaluate the file, selecting the attribute path given with --attr
+    fn eval(&self, options: &EvalOptions) -> color_eyre::Result<NixObject> {
+        let val = eval_file(self.file(), options)?;
+        match &self.attr {
+            Some(path) => select_attr_path(&val, path),
+            None => Ok(val),
         }
     }
-}
 
-#[derive(Clone, Debug, Default)]
-struct Scope {
-    items: HashMap<String, NixObject>,
-    with_namespaces: Vec<NixSet>,
+    // The files the evaluation reads, which --watch watches
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let file = resolve_nix_file(self.file()).unwrap_or_else(|_| self.file().clone());
+        let mut files = vec![file];
+        files.extend(self.config.iter().cloned());
+        files.extend(self.known_packages.iter().cloned());
+        files.extend(self.maintainers.iter().cloned());
+        files.extend(self.overlays.iter().cloned());
+        files
+    }
+
+    fn to_options(&self) -> color_eyre::Result<EvalOptions> {
+        let config = match &self.config {
+            Some(path) => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+            None => None,
+        };
+        let known_packages = match &self.known_packages {
+            Some(path) => Some(parse_package_list(&std::fs::read_to_string(path)?)),
+            None => None,
+        };
+        let maintainers = match &self.maintainers {
+            Some(path) => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+            None => None,
+        };
+        let overlays = self
+            .overlays
+            .iter()
+            .map(std::fs::read_to_string)
+            .collect::<Result<_, _>>()?;
+        Ok(EvalOptions {
+            args: self.args.iter().cloned().collect(),
+            lenient: self.lenient,
+            impure: self.impure,
+            allow_ifd: self.allow_ifd,
+            provenance: self.provenance,
+            system: None,
+            trace: self.verbose,
+            profile: false,
+            store_dir: Some(self.store_dir.clone()),
+            nix_version: Some(self.nix_version.clone()),
+            known_packages,
+            maintainers,
+            nix_path: self
+                .nix_path
+                .clone()
+                .or_else(|| std::env::var("NIX_PATH").ok()),
+            overlays,
+            config,
+        })
+    }
 }
 
-impl Scope {
-    fn new() -> Self {
-        Default::default()
+fn diff_deps(systems: &[String], eval: &EvalArgs) -> color_eyre::Result<()> {
+    let [from, to] = systems else {
+        return Err(eyre!(
+            "expected exactly two systems, found {}",
+            systems.len()
+        ));
+    };
+    let mut options = eval.to_options()?;
+    let mut deps_for = |system: &str| {
+        options.system = Some(system.to_owned());
+        collect_dependencies(&eval.eval(&options)?)
+    };
+    let (from_deps, to_deps) = (deps_for(from)?, deps_for(to)?);
+    for dep in to_deps.difference(&from_deps) {
+        println!("+ {}", dep);
     }
+    for dep in from_deps.difference(&to_deps) {
+        println!("- {}", dep);
+    }
+    Ok(())
+}
 
-    // We handle precedence of items vs. with by promoting items itno a with_namespaces
-    //  entry every time a new with_namespace is added.
-    fn lookup(&self, k: &str) -> Option<NixObject> {
-        if let Some(obj) = self.items.get(k) {
-            return Some(obj.clone());
+// The value is forced completely by printing it, so errors anywhere in it are found
+fn check(eval: &EvalArgs) -> color_eyre::Result<()> {
+    let options = EvalOptions {
+        provenance: true,
+        ..eval.to_options()?
+    };
+    let file = eval.file().display();
+    match eval.eval(&options).and_then(|val| print_value(&val)) {
+        Ok(_) if !errors().is_empty() => {
+            println!("{}: failed", file);
+            print_errors(eval, &errors())?;
+            std::process::exit(1);
+        }
+        Ok(_) if options.lenient => {
+            let warnings = warning_count();
+            let plural = if warnings == 1 { "" } else { "s" };
+            println!("{}: ok, {} warning{}", file, warnings, plural);
         }
-        for namespace in self.with_namespaces.iter() {
-            if let Some(obj) = namespace.lookup(k) {
-                return Some(obj);
+        Ok(_) => println!("{}: ok", file),
+        Err(err) => {
+            println!("{}: failed", file);
+            eprintln!("error: {}", err.root_cause());
+            // The innermost definition is the closest to the error
+            if let Some(location) = err.chain().rev().nth(1) {
+                eprintln!("  {}", location);
             }
+            std::process::exit(1);
         }
-        None
     }
+    Ok(())
 }
 
-fn token_type(expr: &Expr) -> &'static str {
-    match expr {
-        Expr::Apply(_) => "apply",
-        Expr::Assert(_) => "assert",
-        Expr::Error(_) => "error",
-        Expr::IfElse(_) => "ifelse",
-        Expr::Select(_) => "select",
-        Expr::Str(_) => "str",
-        Expr::Path(_) => "path",
-        Expr::Literal(_) => "literal",
-        Expr::Lambda(_) => "lambda",
-        Expr::LegacyLet(_) => "legacylet",
-        Expr::LetIn(_) => "letin",
-        Expr::List(_) => "list",
-        Expr::BinOp(_) => "binop",
-        Expr::Paren(_) => "paren",
-        Expr::Root(_) => "root",
-        Expr::AttrSet(_) => "attrset",
-        Expr::UnaryOp(_) => "unaryop",
-        Expr::Ident(_) => "ident",
-        Expr::With(_) => "with",
-        Expr::HasAttr(_) => "hasattr",
+// The errors are grouped by their kind, each with the line of the value that failed
+fn print_errors(eval: &EvalArgs, errors: &[Diagnostic]) -> color_eyre::Result<()> {
+    let source = std::fs::read_to_string(resolve_nix_file(eval.file())?)?;
+    let lines: Vec<_> = source.lines().collect();
+    let (red, bold, reset) = if std::io::stderr().is_terminal() {
+        ("\x1b[1;31m", "\x1b[1m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+    let plural = if errors.len() == 1 { "" } else { "s" };
+    eprintln!(
+        "{}error{}: {} error{} found",
+        red,
+        reset,
+        errors.len(),
+        plural
+    );
+    let mut by_kind: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for error in errors.iter().take(eval.max_errors) {
+        by_kind.entry(error.kind).or_default().push(error);
+    }
+    for (kind, errors) in by_kind {
+        eprintln!("{}{} ({}){}", bold, kind, errors.len(), reset);
+        for error in errors {
+            let Some(origin) = error.origin else {
+                eprintln!("  {}", error.message);
+                continue;
+            };
+            eprintln!("  at {}: {}", origin, error.message);
+            if let Some(line) = lines.get(origin.line - 1) {
+                let number = origin.line.to_string();
+                eprintln!("  {} | {}", number, line);
+                eprintln!(
+                    "  {} | {}{}^{}",
+                    " ".repeat(number.len()),
+                    " ".repeat(origin.column - 1),
+                    red,
+                    reset
+                );
+            }
+        }
+    }
+    if errors.len() > eval.max_errors {
+        eprintln!("... and {} more", errors.len() - eval.max_errors);
+    }
+    Ok(())
+}
+
+// The byte range of the expression defining a value, which is the largest one starting
+// where the value is defined
+fn definition_range(source: &str, origin: Provenance) -> Option<(usize, usize)> {
+    let line_start: usize = source
+        .split_inclusive('\n')
+        .take(origin.line - 1)
+        .map(str::len)
+        .sum();
+    let start = line_start + origin.column - 1;
+    let root = rnix::Root::parse(source).syntax();
+    let token = root
+        .token_at_offset(u32::try_from(start).ok()?.into())
+        .right_biased()?;
+    let node = token
+        .parent_ancestors()
+        .take_while(|node| usize::from(node.text_range().start()) == start)
+        .filter(|node| rnix::ast::Expr::can_cast(node.kind()))
+        .last()?;
+    let range = node.text_range();
+    Some((range.start().into(), range.end().into()))
+}
+
+// The kind of error and the position and byte range in the file it is at, if those are
+// known
+fn error_json(
+    eval: &EvalArgs,
+    kind: &str,
+    message: &str,
+    span: Option<(Provenance, Option<(usize, usize)>)>,
+) -> serde_json::Value {
+    let file = resolve_nix_file(eval.file()).unwrap_or_else(|_| eval.file().clone());
+    let span = span.map(|(origin, range)| {
+        serde_json::json!({
+            "file": file.display().to_string(),
+            "line": origin.line,
+            "column": origin.column,
+            "range": range.map(|(start, end)| [start, end]),
+        })
+    });
+    serde_json::json!({ "kind": kind, "message": message, "span": span })
+}
+
+// Evaluation errors are located at the definition of the value that failed, which is only
+// known when provenance is tracked
+fn eval_error_json(
+    eval: &EvalArgs,
+    error: Option<&EvalError>,
+    message: &str,
+    origin: Option<Provenance>,
+) -> serde_json::Value {
+    let source = resolve_nix_file(eval.file()).and_then(|file| Ok(std::fs::read_to_string(file)?));
+    let span = origin.map(|origin| {
+        let range = source
+            .as_ref()
+            .ok()
+            .and_then(|source| definition_range(source, origin));
+        (origin, range)
+    });
+    error_json(eval, error.map_or("Other", EvalError::kind), message, span)
+}
+
+// Parse errors know the tokens they are at, except at the end of the file
+fn parse_error_json(eval: &EvalArgs, err: &ParseError) -> color_eyre::Result<serde_json::Value> {
+    let source = std::fs::read_to_string(resolve_nix_file(eval.file())?)?;
+    let range = match err {
+        ParseError::Unexpected(range)
+        | ParseError::UnexpectedExtra(range)
+        | ParseError::UnexpectedWanted(_, range, _)
+        | ParseError::UnexpectedDoubleBind(range)
+        | ParseError::DuplicatedArgs(range, _) => (range.start().into(), range.end().into()),
+        _ => (source.len(), source.len()),
+    };
+    let before = &source[..range.0];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let origin = Provenance {
+        line: before.matches('\n').count() + 1,
+        column: range.0 - line_start + 1,
+    };
+    let span = Some((origin, Some(range)));
+    Ok(error_json(eval, "ParseError", &err.to_string(), span))
+}
+
+fn report_error_json(
+    eval: &EvalArgs,
+    err: &color_eyre::Report,
+) -> color_eyre::Result<serde_json::Value> {
+    if let Some(err) = err.root_cause().downcast_ref::<ParseError>() {
+        return parse_error_json(eval, err);
+    }
+    let error = err.root_cause().downcast_ref::<EvalError>();
+    let message = err.root_cause().to_string();
+    Ok(eval_error_json(eval, error, &message, error_origin(err)))
+}
+
+fn diagnostics_json(eval: &EvalArgs, diagnostics: &[Diagnostic]) -> serde_json::Value {
+    diagnostics
+        .iter()
+        .map(|d| eval_error_json(eval, d.error.as_ref(), &d.message, d.origin))
+        .collect()
+}
+
+fn print_profile() {
+    eprintln!("{:<10} {:>8} {:>12}", "kind", "count", "time");
+    for entry in profile() {
+        eprintln!(
+            "{:<10} {:>8} {:>12}",
+            entry.kind,
+            entry.count,
+            format!("{:.3?}", entry.time)
+        );
     }
 }
 
-fn eval_object(scope: &Scope, expr: Expr) -> color_eyre::Result<NixObject> {
-    match expr {
-        Expr::With(with) => {
-            let namespace = with.namespace().ok_or(eyre!("with has no namespace"))?;
-            let mut new_scope = scope.clone();
-            let old_items = std::mem::replace(&mut new_scope.items, HashMap::new());
-            new_scope.with_namespaces.push(NixSet::Dyn(old_items));
-            new_scope
-                .with_namespaces
-                .push(eval_object(scope, namespace)?.try_into_set()?);
-            eval_object(&new_scope, with.body().ok_or(eyre!("with has no body"))?)
+// Evaluate and print the file as the options say, returning whether the checks passed
+fn run(cli: &Cli) -> color_eyre::Result<bool> {
+    let mut options = EvalOptions {
+        system: Some(cli.system.clone()),
+        ..cli.eval.to_options()?
+    };
+    // Collected errors are shown with where the values that failed are defined
+    options.provenance |= cli.report.is_some()
+        || cli.strict_deps
+        || options.lenient
+        || cli.error_format == ErrorFormat::Json;
+    options.profile = cli.profile;
+    if cli.dump_ast || cli.dump_ast_only {
+        let input = std::fs::read_to_string(resolve_nix_file(cli.eval.file())?)?;
+        print!("{:#?}", rnix::Root::parse(&input).syntax());
+        if cli.dump_ast_only {
+            return Ok(true);
         }
-        Expr::LetIn(letin) => {
-            let mut new_scope = scope.clone();
-            for entry in letin.entries().into_iter() {
-                match entry {
-                    Entry::AttrpathValue(attrval) => {
-                        let attrs = attrval
-                            .attrpath()
-                            .ok_or(eyre!("let entry without attrpath"))?
-                            .attrs()
-                            .collect::<Vec<_>>();
-                        if attrs.len() != 1 {
-                            return Err(eyre!(
-                                "expect single attr in let attrpath, found {}",
-                                attrs.len()
-                            ));
-                        }
-                        let Attr::Ident(ident) = attrs.first().unwrap() else {
-                            return Err(eyre!("unexpected let attr type"));
-                        };
-                        let val = attrval.value().ok_or(eyre!("let binding without value"))?;
-                        new_scope
-                            .items
-                            .insert(ident.to_string(), eval_object(&new_scope, val)?);
-                    }
-                    Entry::Inherit(inherit) => {
-                        todo!();
-                    }
+    }
+    let result = cli.eval.eval(&options)?;
+    if cli.strict_deps {
+        let unresolved = unresolved_dependencies(&result)?;
+        if !unresolved.is_empty() {
+            eprintln!("error: unresolved dependencies:");
+            for (name, origin) in unresolved {
+                match origin {
+                    Some(origin) => eprintln!("  {} at {}", name, origin),
+                    None => eprintln!("  {}", name),
                 }
             }
-            eval_object(scope, letin.body().ok_or(eyre!("letin without body"))?)
+            return Ok(false);
         }
-        Expr::AttrSet(set) => {
-            let is_rec = set.rec_token().is_some();
-            let mut new_scope = scope.clone();
-            let mut set_vals = HashMap::<String, NixObject>::new();
-            for entry in set.entries().into_iter() {
-                println!("{:#?}", entry);
-                match entry {
-                    Entry::AttrpathValue(attrval) => {
-                        let val = eval_object(
-                            &new_scope,
-                            attrval.value().ok_or(eyre!("set entry without value"))?,
-                        )?;
-                        let entry: Option<
-                            std::collections::hash_map::Entry<'_, String, NixObject>,
-                        > = None;
-                        for attr in attrval
-                            .attrpath()
-                            .ok_or(eyre!("set entry without attrpath"))?
-                            .attrs()
-                            .into_iter()
-                        {
-                            let Attr::Ident(attr_ident) = attr else {
-                                return Err(eyre!("unimplemented"));
-                            };
-                            let attr_ident = attr_ident.to_string();
-                            entry = match entry {
-                                None => Some(set_vals.entry(attr_ident)),
-                                Some(entry) => {
-                                    let NixObject::Set(s) = entry.or_insert_with(|| NixObject::Set(NixSet::Dyn(HashMap::new()))) else {
-                                        return Err(eyre!("cannot access attributes of non-set value"));
-                                    };
-                                }
-                            };
-                        }
-                    }
-                    _ => todo!(),
-                }
-            }
-            todo!();
+    }
+    if let Some(ReportFormat::Json) = cli.report {
+        println!("{}", serde_json::to_string_pretty(&report(&result)?)?);
+    } else if cli.graph {
+        print!("{}", dependency_graph(&result)?);
+    } else if cli.json_schema {
+        println!("{}", serde_json::to_string_pretty(&json_schema(&result)?)?);
+    } else {
+        println!("{}", print_value(&result)?);
+    }
+    if cli.profile {
+        print_profile();
+    }
+    let errors = errors();
+    if !errors.is_empty() {
+        match cli.error_format {
+            ErrorFormat::Json => eprintln!("{}", diagnostics_json(&cli.eval, &errors)),
+            ErrorFormat::Human => print_errors(&cli.eval, &errors)?,
         }
-        Expr::Ident(ident) => scope
-            .lookup(ident.to_string().as_ref())
-            .ok_or(eyre!("value not in scope: {}", ident)),
-        Expr::Select(s) => {
-            println!("{:#?}", s);
-            let initial = eval_object(scope, s.expr().ok_or(eyre!("select without expr"))?)?;
-            s.attrpath()
-                .ok_or(eyre!("select without attrpath"))?
-                .attrs()
-                .into_iter()
-                .try_fold::<_, _, color_eyre::Result<_>>(initial, |prev, attr| match attr {
-                    Attr::Ident(i) => prev
-                        .clone()
-                        .try_into_set()?
-                        .lookup(i.to_string().as_ref())
-                        .ok_or(eyre!("value not in scope: {}", i)),
-                    Attr::Dynamic(_) => todo!(),
-                    Attr::Str(_) => todo!(),
-                })
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Editors often save by replacing the file, so the directories are watched rather than
+// the files themselves
+fn watch(cli: &Cli) -> color_eyre::Result<()> {
+    let given_files = cli
+        .eval
+        .watched_files()
+        .into_iter()
+        .map(std::path::absolute)
+        .collect::<Result<HashSet<_>, _>>()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched_dirs = HashSet::new();
+    let stdout_is_terminal = std::io::stdout().is_terminal();
+    loop {
+        if let Err(err) = run(cli) {
+            eprintln!("error: {:#}", err);
         }
-        Expr::Apply(a) => {
-            let lambda = eval_object(scope, a.lambda().ok_or(eyre!("apply without lambda"))?)?;
-            let argument =
-                eval_object(scope, a.argument().ok_or(eyre!("apply without argument"))?)?;
-            lambda.apply(argument)
+        // The files the evaluation imported are only known once it is done, and may differ
+        // between evaluations
+        let files: HashSet<_> = given_files
+            .iter()
+            .cloned()
+            .chain(imported_files())
+            .collect();
+        let dirs: HashSet<_> = files
+            .iter()
+            .filter_map(|file| file.parent())
+            .map(PathBuf::from)
+            .collect();
+        for dir in watched_dirs.difference(&dirs) {
+            watcher.unwatch(dir)?;
+        }
+        for dir in dirs.difference(&watched_dirs) {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        watched_dirs = dirs;
+        // Wait for a change to one of the files, then for the changes to settle so that a
+        // save is only evaluated once
+        loop {
+            let event: notify::Event = rx.recv()??;
+            // Reading the files for the evaluation is an event too
+            let changed = matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(
+                        ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any
+                    )
+            );
+            if changed && event.paths.iter().any(|path| files.contains(path)) {
+                break;
+            }
+        }
+        while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        if stdout_is_terminal {
+            print!("\x1b[2J\x1b[H");
+        } else {
+            println!("---");
         }
-        expr => return Err(eyre!("cannot eval object of type: {}", token_type(&expr))),
     }
 }
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
-
-    let input = include_str!("../test.nix.txt");
-    let ast = rnix::Root::parse(&input).ok()?;
-    let expr = ast.expr().ok_or(eyre!("file is empty"))?;
-    let Expr::Lambda(lambda) = expr else {
-        return Err(eyre!("file does not contain a lambda"));
-    };
-
-    let mut scope = Scope::new();
-    let param = lambda
-        .param()
-        .ok_or(eyre!("top-level lambda does not have a param"))?;
-    let Param::Pattern(pat) = param else {
-        return Err(eyre!("top-level lambda does not destructure its argument"));
-    };
-    if let Some(bind) = pat.pat_bind() {
-        scope.items.insert(
-            bind.ident().ok_or(eyre!("bind without ident"))?.to_string(),
-            NixObject::Set(NixSet::CallpkgArgs),
-        );
+    let cli = Cli::parse();
+    match &cli.command {
+        Some(Command::DiffDeps { systems, eval }) => return diff_deps(systems, eval),
+        Some(Command::Check { eval }) => return check(eval),
+        None => {}
     }
-    for e in pat.pat_entries() {
-        let ident = e
-            .ident()
-            .ok_or(eyre!("pat entry without ident"))?
-            .to_string();
-        let val = NixObject::Set(
-            CALLPACKAGE_ARGS
-                .get(&ident)
-                .ok_or(eyre!("unknown callPackage arg: {}", ident))?
-                .clone(),
-        );
-        scope.items.insert(ident, val);
+    if cli.watch {
+        return watch(&cli);
+    }
+    match run(&cli) {
+        Ok(true) => Ok(()),
+        Ok(false) => std::process::exit(1),
+        Err(err) if cli.error_format == ErrorFormat::Json => {
+            let error = report_error_json(&cli.eval, &err)?;
+            if cli.eval.lenient {
+                eprintln!("{}", serde_json::Value::Array(vec![error]));
+            } else {
+                eprintln!("{}", error);
+            }
+            std::process::exit(1);
+        }
+        Err(err) => Err(err),
     }
-
-    let body = lambda.body().ok_or(eyre!("lambda without body"))?;
-    eval_object(&scope, body)?;
-    Ok(())
 }
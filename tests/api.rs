@@ -0,0 +1,788 @@
+use rnix_experiments::{
+    availability, collect_dependencies, errors, eval_file, eval_object, eval_str, json_schema,
+    print_value, profile, report, warning_count, ArgKind, Availability, Diagnostic, EvalError,
+    EvalOptions, Provenance, Scope,
+};
+
+fn eval_with(source: &str, options: &EvalOptions) -> String {
+    print_value(&eval_str(source, options).unwrap()).unwrap()
+}
+
+#[test]
+fn non_lambda_files_evaluate_directly() {
+    let path =
+        std::env::temp_dir().join(format!("rnix-experiments-api-{}.nix", std::process::id()));
+    std::fs::write(&path, "let a = 1; in { b = a + 1; }").unwrap();
+    let val = eval_file(&path, &EvalOptions::default());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(print_value(&val.unwrap()).unwrap(), "{ b = 2; }");
+}
+
+#[test]
+fn options_control_root_arguments() {
+    let source = "{ lib, hello, extra, ... }: [ hello extra ]";
+    assert!(eval_str(source, &EvalOptions::default()).is_err());
+
+    let options = EvalOptions {
+        args: [("hello".to_owned(), ArgKind::Pkg)].into_iter().collect(),
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &options),
+        "[ «pkgs.hello» «unknown extra» ]"
+    );
+}
+
+#[test]
+fn options_provide_config_values() {
+    let options = EvalOptions {
+        config: Some(serde_json::json!({ "services": { "foo": { "enable": true } } })),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with("{ config, ... }: config.services.foo.enable", &options),
+        "true"
+    );
+    // Values do not leak into later evaluations
+    assert_eq!(
+        eval_with(
+            "{ config, ... }: config.services.foo.enable",
+            &EvalOptions::default()
+        ),
+        "«config.services.foo.enable»"
+    );
+}
+
+#[test]
+fn known_packages_catch_typos() {
+    let known = Some(["stdenv".to_owned()].into_iter().collect());
+    let strict = EvalOptions {
+        known_packages: known.clone(),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with("{ pkgs, ... }: pkgs.stdenv", &strict),
+        "«pkgs.stdenv»"
+    );
+    let err = eval_str("{ pkgs, ... }: pkgs.stdnev", &strict).unwrap_err();
+    assert!(err.to_string().contains("attribute 'stdnev' missing"));
+    assert_eq!(
+        eval_with(
+            "{ pkgs, ... }: [ (pkgs ? stdnev) (pkgs.stdnev or 1) ]",
+            &strict
+        ),
+        "[ false 1 ]"
+    );
+
+    let lenient = EvalOptions {
+        known_packages: known,
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with("{ pkgs, ... }: pkgs.stdnev", &lenient),
+        "«pkgs.stdnev»"
+    );
+}
+
+#[test]
+fn get_env_is_only_read_in_impure_mode() {
+    std::env::set_var("RNIX_EXPERIMENTS_TEST_VAR", "set");
+    let source = r#"[ (builtins.getEnv "RNIX_EXPERIMENTS_TEST_VAR") (builtins.getEnv "RNIX_EXPERIMENTS_UNSET_VAR") ]"#;
+    assert_eq!(
+        eval_with(source, &EvalOptions::default()),
+        r#"[ «unknown builtins.getEnv "RNIX_EXPERIMENTS_TEST_VAR"» «unknown builtins.getEnv "RNIX_EXPERIMENTS_UNSET_VAR"» ]"#
+    );
+    let impure = EvalOptions {
+        impure: true,
+        ..Default::default()
+    };
+    assert_eq!(eval_with(source, &impure), r#"[ "set" "" ]"#);
+}
+
+#[test]
+fn platform_conditionals_follow_the_system() {
+    let source = r#"{ lib, stdenv, ... }: {
+      system = builtins.currentSystem;
+      host = stdenv.hostPlatform.system;
+      deps = [ "common" ] ++ lib.optionals stdenv.isDarwin [ "darwin" ]
+        ++ lib.optional stdenv.isLinux "linux"
+        ++ lib.optional stdenv.hostPlatform.isAarch64 "arm";
+    }"#;
+    let for_system = |system: &str| EvalOptions {
+        system: Some(system.to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &for_system("x86_64-linux")),
+        r#"{ deps = [ "common" "linux" ]; host = "x86_64-linux"; system = "x86_64-linux"; }"#
+    );
+    assert_eq!(
+        eval_with(source, &for_system("aarch64-darwin")),
+        r#"{ deps = [ "common" "darwin" "arm" ]; host = "aarch64-darwin"; system = "aarch64-darwin"; }"#
+    );
+}
+
+#[test]
+fn dependencies_are_found_in_interpolated_strings() {
+    let val = eval_str(
+        r#"{ pkgs, ... }: {
+          script = "${pkgs.coreutils}/bin/ls ${pkgs.gnused}/bin/sed";
+          inputs = [ pkgs.zlib ];
+        }"#,
+        &EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        collect_dependencies(&val)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["coreutils", "gnused", "zlib"]
+    );
+}
+
+#[test]
+fn provenance_points_at_definitions() {
+    let source = "{ lib, ... }: {\n  a = 1;\n  b = missing;\n}";
+    let options = EvalOptions {
+        provenance: true,
+        ..Default::default()
+    };
+    let val = eval_str(source, &options).unwrap();
+    let attrs = val.clone().try_into_set().unwrap();
+    assert_eq!(
+        attrs.lookup("a").unwrap().provenance(),
+        Some(Provenance { line: 2, column: 7 })
+    );
+    let err = print_value(&val).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "while evaluating the value defined at line 3, column 7"
+    );
+    assert!(err.downcast_ref::<EvalError>().is_some());
+
+    let untracked = eval_str(source, &EvalOptions::default()).unwrap();
+    let attrs = untracked.try_into_set().unwrap();
+    assert_eq!(attrs.lookup("a").unwrap().provenance(), None);
+}
+
+#[test]
+fn with_on_config_resolves_to_option_paths() {
+    let module = "{ config, lib, ... }: {
+      options.foo.baz = lib.mkOption { default = 1; };
+      config.x = with config.foo; [ bar baz true ];
+    }";
+    let module = eval_str(module, &EvalOptions::default()).unwrap();
+    let config = module.try_into_set().unwrap().lookup("config").unwrap();
+    assert_eq!(
+        print_value(&config).unwrap(),
+        "{ x = [ «config.foo.bar» 1 true ]; }"
+    );
+    // foo.bar is not declared
+    assert_eq!(warning_count(), 1);
+}
+
+#[test]
+fn overlays_add_packages_to_pkgs() {
+    let options = EvalOptions {
+        known_packages: Some(["openssl".to_owned()].into_iter().collect()),
+        overlays: vec![
+            "self: super: { mylib = { pname = \"mylib\"; buildInputs = [ self.openssl ]; }; }"
+                .to_owned(),
+            "self: super: { mylib = super.mylib // { patched = true; }; }".to_owned(),
+        ],
+        ..Default::default()
+    };
+    let source = "{ pkgs, ... }: { buildInputs = [ pkgs.mylib pkgs.openssl ]; patched = pkgs.mylib.patched; }";
+    let val = eval_str(source, &options).unwrap();
+    assert_eq!(
+        print_value(&val).unwrap(),
+        "{ buildInputs = [ { buildInputs = [ «pkgs.openssl» ]; patched = true; pname = \"mylib\"; } «pkgs.openssl» ]; patched = true; }"
+    );
+    assert_eq!(
+        collect_dependencies(&val)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["mylib", "openssl"]
+    );
+}
+
+#[test]
+fn files_are_reported_with_their_content() {
+    let val = eval_str(
+        r#"{ script = "sh ${builtins.toFile "hook.sh" "echo hi"}"; }"#,
+        &EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        print_value(&val).unwrap(),
+        r#"{ script = "sh /nix/store/33333333333333333333333333333333-hook.sh"; }"#
+    );
+    assert_eq!(
+        report(&val).unwrap()["files"],
+        serde_json::json!([{
+            "path": "/nix/store/33333333333333333333333333333333-hook.sh",
+            "name": "hook.sh",
+            "content": "echo hi",
+        }])
+    );
+}
+
+#[test]
+fn trivial_builders_make_derivations() {
+    let val = eval_str(
+        r#"{ pkgs }: {
+  type = (pkgs.writeText "app.conf" "port = 80").type;
+  wrapper = "${pkgs.writeShellScriptBin "app" "exec app"}/bin/app";
+}"#,
+        &EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        print_value(&val).unwrap(),
+        r#"{ type = "derivation"; wrapper = "/nix/store/33333333333333333333333333333333-app/bin/app"; }"#
+    );
+    assert_eq!(
+        report(&val).unwrap()["files"],
+        serde_json::json!([
+            {
+                "path": "/nix/store/33333333333333333333333333333333-app.conf",
+                "name": "app.conf",
+                "content": "port = 80",
+            },
+            {
+                "path": "/nix/store/33333333333333333333333333333333-app/bin/app",
+                "name": "app",
+                "content": "#!/nix/store/00000000000000000000000000000000-bash/bin/bash\nexec app",
+            },
+        ])
+    );
+    let err = eval_str(
+        r#"{ pkgs }: pkgs.writeTextFile { name = "empty"; }"#,
+        &EvalOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("requires the attribute 'text'"));
+}
+
+#[test]
+fn aggregate_builders_capture_their_inputs() {
+    let val = eval_str(
+        r#"{ pkgs }: rec {
+  env = pkgs.symlinkJoin { name = "env"; paths = [ pkgs.hello pkgs.git ]; };
+  check = pkgs.runCommand "check" { nativeBuildInputs = [ pkgs.jq ]; } "ls ${env}/bin > $out";
+}"#,
+        &EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        collect_dependencies(&val)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["git", "hello", "jq"]
+    );
+    assert_eq!(
+        print_value(&eval_str(
+            r#"{ pkgs }: (pkgs.runCommand "check" { } "ls ${pkgs.symlinkJoin { name = "env"; paths = [ ]; }}").buildCommand"#,
+            &EvalOptions::default(),
+        )
+        .unwrap())
+        .unwrap(),
+        r#""ls /nix/store/44444444444444444444444444444444-env""#
+    );
+    let err = eval_str(
+        r#"{ pkgs }: pkgs.symlinkJoin { name = "env"; }"#,
+        &EvalOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("requires the attribute 'paths'"));
+}
+
+#[test]
+fn profile_counts_evaluations_per_kind() {
+    let options = EvalOptions {
+        profile: true,
+        ..Default::default()
+    };
+    let val = eval_str("let f = x: x + 1; in map f [ 1 2 3 ]", &options).unwrap();
+    assert_eq!(print_value(&val).unwrap(), "[ 2 3 4 ]");
+    let counts: std::collections::HashMap<_, _> = profile()
+        .into_iter()
+        .map(|entry| (entry.kind, entry.count))
+        .collect();
+    assert_eq!(
+        (counts["binop"], counts["lambda"], counts["literal"]),
+        (3, 1, 6)
+    );
+
+    eval_str("1", &EvalOptions::default()).unwrap();
+    assert!(profile().is_empty());
+}
+
+#[test]
+fn store_dir_and_nix_version_are_configurable() {
+    let source = "[ builtins.storeDir builtins.nixVersion builtins.langVersion ]";
+    assert_eq!(
+        eval_with(source, &EvalOptions::default()),
+        r#"[ "/nix/store" "2.24.0" 6 ]"#
+    );
+    let options = EvalOptions {
+        store_dir: Some("/gnu/store".to_owned()),
+        nix_version: Some("2.3.16".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &options),
+        r#"[ "/gnu/store" "2.3.16" 6 ]"#
+    );
+}
+
+#[test]
+fn unavailable_packages_are_warned_about() {
+    let source = r#"{ lib, stdenv }: stdenv.mkDerivation {
+  name = "demo";
+  meta = { broken = stdenv.isDarwin; platforms = lib.platforms.unix; };
+}"#;
+    let options = |system: &str| EvalOptions {
+        system: Some(system.to_owned()),
+        ..Default::default()
+    };
+    let val = eval_str(source, &options("x86_64-linux")).unwrap();
+    assert_eq!(warning_count(), 0);
+    assert_eq!(
+        availability(&val).unwrap(),
+        Some(Availability {
+            broken: false,
+            supported: true
+        })
+    );
+
+    let val = eval_str(source, &options("aarch64-darwin")).unwrap();
+    assert_eq!(warning_count(), 1);
+    assert_eq!(
+        report(&val).unwrap()["availability"],
+        serde_json::json!({ "broken": true, "supported": true })
+    );
+
+    let val = eval_str(source, &options("x86_64-windows")).unwrap();
+    assert_eq!(warning_count(), 1);
+    assert_eq!(
+        report(&val).unwrap()["warnings"][0]["message"],
+        "the package is not available on x86_64-windows"
+    );
+    assert_eq!(
+        availability(&eval_str("1", &options("x86_64-linux")).unwrap()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn package_meta_is_reported() {
+    let val = eval_str(
+        r#"{ lib, stdenv }: stdenv.mkDerivation {
+  name = "demo";
+  meta = with lib; {
+    description = "A demo";
+    license = with licenses; [ mit asl20 unfree ];
+    maintainers = with maintainers; [ alice ];
+  };
+}"#,
+        &EvalOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        report(&val).unwrap()["meta"],
+        serde_json::json!({
+            "description": "A demo",
+            "homepage": null,
+            "license": ["MIT", "Apache-2.0", "unfree"],
+            "maintainers": ["alice"],
+        })
+    );
+}
+
+#[test]
+fn unknown_licenses_are_unknown_in_lenient_mode() {
+    let source = "{ pkgs }: [ pkgs.lib.licenses.bsd3.spdxId pkgs.lib.licenses.madeUp ]";
+    let lenient = EvalOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &lenient),
+        r#"[ "BSD-3-Clause" «unknown lib.licenses.madeUp» ]"#
+    );
+    let strict = eval_str(source, &EvalOptions::default())
+        .and_then(|val| print_value(&val))
+        .unwrap_err();
+    assert_eq!(strict.to_string(), "attribute 'madeUp' missing");
+}
+
+#[test]
+fn unknown_platform_groups_are_supported_in_lenient_mode() {
+    let options = EvalOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let val = eval_str(
+        r#"{ lib, stdenv }: stdenv.mkDerivation { name = "demo"; meta.platforms = lib.platforms.plan9; }"#,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(
+        availability(&val).unwrap(),
+        Some(Availability {
+            broken: false,
+            supported: true
+        })
+    );
+}
+
+#[test]
+fn maintainers_are_checked_against_the_list() {
+    let source = r#"{ lib }: with lib.maintainers; [ alice.email bob.github ]"#;
+    assert_eq!(
+        eval_with(
+            "{ lib }: lib.maintainers.anyone.github",
+            &EvalOptions::default()
+        ),
+        r#""anyone""#
+    );
+    let maintainers = serde_json::json!({
+        "alice": { "name": "Alice", "email": "alice@example.org", "github": "alice" },
+    });
+    let options = EvalOptions {
+        maintainers: Some(maintainers),
+        lenient: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &options),
+        r#"[ "alice@example.org" "bob" ]"#
+    );
+    assert_eq!(warning_count(), 1);
+
+    let strict = EvalOptions {
+        lenient: false,
+        ..options
+    };
+    let err = eval_str(source, &strict)
+        .and_then(|val| print_value(&val))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "value not in scope: bob");
+}
+
+#[test]
+fn top_level_defaults_refer_to_other_arguments() {
+    let source = "{ enableFoo ? stdenv.isLinux, stdenv, flags ? if enableFoo then [ \"--foo\" ] else [ ] }: flags";
+    let options = |system: &str| EvalOptions {
+        system: Some(system.to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &options("x86_64-linux")),
+        r#"[ "--foo" ]"#
+    );
+    assert_eq!(eval_with(source, &options("aarch64-darwin")), "[ ]");
+}
+
+#[test]
+fn search_paths_resolve_against_the_nix_path() {
+    let dir = std::env::temp_dir().join(format!("rnix-experiments-nixpath-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nixpkgs/lib")).unwrap();
+    std::fs::create_dir_all(dir.join("channels/home-manager")).unwrap();
+    let options = EvalOptions {
+        nix_path: Some(format!(
+            "nixpkgs={}/nixpkgs:{}/channels",
+            dir.display(),
+            dir.display()
+        )),
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with("[ <nixpkgs> <nixpkgs/lib> <home-manager> ]", &options),
+        format!(
+            "[ {0}/nixpkgs {0}/nixpkgs/lib {0}/channels/home-manager ]",
+            dir.display()
+        )
+    );
+    assert_eq!(
+        eval_with("map (e: e.prefix) builtins.nixPath", &options),
+        r#"[ "nixpkgs" "" ]"#
+    );
+    let err = eval_str("<nixos-hardware/lenovo>", &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "file 'nixos-hardware/lenovo' was not found in the Nix search path"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn imports_are_evaluated_once_per_file() {
+    let dir = std::env::temp_dir().join(format!("rnix-experiments-import-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(
+        dir.join("lib/helper.nix"),
+        r#"builtins.toFile "helper" "x""#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("lib/default.nix"),
+        "{ helper = import ./helper.nix; }",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("env.nix"),
+        r#"builtins.toFile "env" (builtins.getEnv "HOME")"#,
+    )
+    .unwrap();
+
+    let source = format!(
+        "[ (import {0}/lib/helper.nix) (import {0}/lib).helper ]",
+        dir.display()
+    );
+    let val = eval_str(&source, &EvalOptions::default()).unwrap();
+    assert_eq!(
+        print_value(&val).unwrap(),
+        r#"[ "/nix/store/33333333333333333333333333333333-helper" "/nix/store/33333333333333333333333333333333-helper" ]"#
+    );
+    assert_eq!(report(&val).unwrap()["files"].as_array().unwrap().len(), 1);
+
+    // Imports that read the environment are evaluated again every time
+    let source = format!(
+        "[ (import {0}/env.nix) (import {0}/env.nix) ]",
+        dir.display()
+    );
+    let options = EvalOptions {
+        impure: true,
+        ..Default::default()
+    };
+    let val = eval_str(&source, &options).unwrap();
+    print_value(&val).unwrap();
+    assert_eq!(report(&val).unwrap()["files"].as_array().unwrap().len(), 2);
+
+    // Reads made after the file is evaluated are only done once, as the value is cached
+    // before them
+    std::fs::write(
+        dir.join("lazy_env.nix"),
+        r#"{ file = builtins.toFile "env" (builtins.getEnv "HOME"); }"#,
+    )
+    .unwrap();
+    let source = format!(
+        "[ (import {0}/lazy_env.nix).file (import {0}/lazy_env.nix).file ]",
+        dir.display()
+    );
+    let val = eval_str(&source, &options).unwrap();
+    print_value(&val).unwrap();
+    assert_eq!(report(&val).unwrap()["files"].as_array().unwrap().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn string_context_makes_dependencies() {
+    let source = r#"{ pkgs, ... }: {
+  buildPhase = "${pkgs.gcc}/bin/cc -I${pkgs.zlib}/include";
+  doc = builtins.unsafeDiscardStringContext "see ${pkgs.python3}";
+  drv = builtins.unsafeDiscardOutputDependency "${pkgs.perl}";
+}"#;
+    let val = eval_str(source, &EvalOptions::default()).unwrap();
+    assert_eq!(
+        collect_dependencies(&val)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["gcc", "perl", "zlib"]
+    );
+}
+
+#[test]
+fn lenient_printing_collects_errors() {
+    let options = EvalOptions {
+        lenient: true,
+        provenance: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with("{\n  a = throw \"no\";\n  b = [ missing ];\n}", &options),
+        "{ a = «error»; b = [ «error» ]; }"
+    );
+    assert_eq!(
+        errors(),
+        [
+            Diagnostic {
+                kind: "thrown error",
+                message: "no".to_owned(),
+                origin: Some(Provenance { line: 2, column: 7 }),
+                error: Some(EvalError::Throw("no".to_owned())),
+            },
+            Diagnostic {
+                kind: "not in scope",
+                message: "value not in scope: missing".to_owned(),
+                origin: Some(Provenance { line: 3, column: 9 }),
+                error: Some(EvalError::NotInScope("missing".to_owned())),
+            },
+        ]
+    );
+    assert!(eval_str("{ a = throw \"no\"; }", &EvalOptions::default())
+        .and_then(|val| print_value(&val))
+        .is_err());
+}
+
+#[test]
+fn roots_evaluate_their_expression() {
+    let root = rnix::Root::parse("let a = 1; in a + 1").tree();
+    let val = eval_object(&Scope::new(), rnix::ast::Expr::Root(root)).unwrap();
+    assert_eq!(print_value(&val).unwrap(), "2");
+}
+
+#[test]
+fn import_from_derivation_is_detected() {
+    let source = r#"{ pkgs, ... }: {
+  generated = import "${pkgs.runCommand "gen" { } "echo {} > $out"}" { };
+  fromPkg = (import "${pkgs.hello}/share/default.nix").attr;
+}"#;
+    let err = eval_str(source, &EvalOptions::default())
+        .and_then(|val| print_value(&val))
+        .unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "cannot import /nix/store/00000000000000000000000000000000-hello/share/default.nix, which would have to be built first (import from derivation)"
+    );
+
+    let options = EvalOptions {
+        allow_ifd: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        eval_with(source, &options),
+        "{ fromPkg = «unknown import /nix/store/00000000000000000000000000000000-hello/share/default.nix.attr»; generated = «unknown import /nix/store/44444444444444444444444444444444-gen <arg>»; }"
+    );
+    assert_eq!(warning_count(), 2);
+
+    let written = r#"{ pkgs, ... }: import (pkgs.writeText "a.nix" "1 + 2")"#;
+    let err = eval_str(written, &EvalOptions::default()).unwrap_err();
+    assert_eq!(
+        err.root_cause().to_string(),
+        "cannot import /nix/store/33333333333333333333333333333333-a.nix, which would have to be built first (import from derivation)"
+    );
+}
+
+#[test]
+fn files_from_to_file_can_be_imported() {
+    assert_eq!(
+        eval_with(
+            r#"import (builtins.toFile "a.nix" "1 + 2")"#,
+            &EvalOptions::default()
+        ),
+        "3"
+    );
+    assert_eq!(
+        eval_with(
+            r#"let f = builtins.toFile "f.nix" "x: x * 2"; in [ (import f 2) (import "${f}" 3) ]"#,
+            &EvalOptions::default()
+        ),
+        "[ 4 6 ]"
+    );
+}
+
+#[test]
+fn option_types_export_as_json_schema() {
+    let module = r#"{ lib, pkgs, ... }: {
+  options.services.foo.settings = {
+    log = lib.mkOption { type = lib.types.str; default = "warn"; description = "Log level."; };
+    workers = lib.mkOption { type = lib.types.nullOr lib.types.int; default = null; };
+    hosts = lib.mkOption { type = lib.types.listOf lib.types.port; };
+    extra = lib.mkOption { type = lib.types.attrsOf (pkgs.formats.toml { }).type; };
+    module = lib.mkOption { type = lib.types.deferredModule; };
+  };
+}"#;
+    let schema = json_schema(&eval_str(module, &EvalOptions::default()).unwrap()).unwrap();
+    assert_eq!(warning_count(), 1);
+    assert_eq!(
+        schema["properties"]["services"]["properties"]["foo"]["properties"]["settings"],
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "log": { "type": "string", "default": "warn", "description": "Log level." },
+                "workers": {
+                    "anyOf": [{ "type": "integer" }, { "type": "null" }],
+                    "default": null,
+                },
+                "hosts": {
+                    "type": "array",
+                    "items": { "type": "integer", "minimum": 0, "maximum": 65535 },
+                },
+                "extra": { "type": "object", "additionalProperties": {} },
+                "module": {},
+            },
+        })
+    );
+}
+
+#[test]
+fn config_resolves_to_option_defaults_and_supplied_values() {
+    let module = r#"{ config, lib, ... }: {
+  options.services.foo = {
+    port = lib.mkOption { type = lib.types.int; default = 80; };
+    host = lib.mkOption { type = lib.types.str; };
+  };
+  config.x = with config.services; [ foo.port foo.host bar ];
+}"#;
+    let config = |options: &EvalOptions| {
+        let module = eval_str(module, options).unwrap();
+        print_value(&module.try_into_set().unwrap().lookup("config").unwrap()).unwrap()
+    };
+    assert_eq!(
+        config(&EvalOptions::default()),
+        "{ x = [ 80 «config.services.foo.host» «config.services.bar» ]; }"
+    );
+    assert_eq!(warning_count(), 1);
+
+    let options = EvalOptions {
+        config: Some(serde_json::json!({
+            "services": { "foo": { "port": 8080, "host": "example.org" }, "bar": [ true ] },
+        })),
+        ..Default::default()
+    };
+    assert_eq!(
+        config(&options),
+        r#"{ x = [ 8080 "example.org" [ true ] ]; }"#
+    );
+    // Supplying a value doesn't declare the option
+    assert_eq!(warning_count(), 1);
+}
+
+#[test]
+fn options_are_declared_before_config_is_read_eagerly() {
+    let module = r#"{ config, lib, ... }:
+let cfg = config.services.foo; in
+if cfg.enable then {
+  options.services.foo = {
+    enable = lib.mkOption { default = true; };
+    port = lib.mkOption { default = 80; };
+  };
+  config.x = cfg.port;
+} else {
+  options.services.foo.enable = lib.mkOption { default = true; };
+}"#;
+    let module = eval_str(module, &EvalOptions::default()).unwrap();
+    assert_eq!(warning_count(), 0);
+    let config = module.try_into_set().unwrap().lookup("config").unwrap();
+    assert_eq!(print_value(&config).unwrap(), "{ x = 80; }");
+
+    let module = r#"{ config, lib, ... }:
+with lib;
+{ options.foo.enable = mkOption { default = false; }; }
+// mkIf config.foo.enable { config.y = 1; }"#;
+    let module = eval_str(module, &EvalOptions::default()).unwrap();
+    assert_eq!(warning_count(), 0);
+    assert_eq!(
+        print_value(&module.try_into_set().unwrap().lookup("condition").unwrap()).unwrap(),
+        "false"
+    );
+}
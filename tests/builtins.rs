@@ -0,0 +1,889 @@
+mod common;
+
+use common::{eval, eval_err, try_eval};
+
+#[test]
+fn seq_forces_only_the_head() {
+    assert_eq!(
+        eval(r#"builtins.seq { a = throw "boom"; } "ok""#),
+        r#""ok""#
+    );
+    assert_eq!(eval(r#"builtins.seq [ (throw "boom") ] "ok""#), r#""ok""#);
+    assert!(eval_err(r#"builtins.seq (throw "boom") "ok""#).contains("boom"));
+}
+
+#[test]
+fn deep_seq_forces_nested_values() {
+    assert_eq!(eval(r#"builtins.deepSeq { a.b = [ 1 ]; } "ok""#), r#""ok""#);
+    assert!(eval_err(r#"builtins.deepSeq { a.b = [ (throw "boom") ]; } "ok""#).contains("boom"));
+    assert!(eval_err(r#"builtins.force { a = throw "boom"; }"#).contains("boom"));
+}
+
+#[test]
+fn generic_closure_dedups_by_key_breadth_first() {
+    assert_eq!(
+        eval(
+            r#"builtins.genericClosure {
+              startSet = [ { key = 1; } { key = 1; } ];
+              operator = item:
+                if item.key < 3 then [ { key = item.key * 2; } { key = item.key * 2 + 1; } ] else [ ];
+            }"#
+        ),
+        "[ { key = 1; } { key = 2; } { key = 3; } { key = 4; } { key = 5; } ]"
+    );
+}
+
+#[test]
+fn partition_splits_by_predicate() {
+    assert_eq!(
+        eval(r#"builtins.partition (x: x > 2) [ 5 1 3 2 4 ]"#),
+        "{ right = [ 5 3 4 ]; wrong = [ 1 2 ]; }"
+    );
+    assert_eq!(
+        eval(r#"lib.partition (x: x == "a") [ "a" 1 "b" "a" ]"#),
+        r#"{ right = [ "a" "a" ]; wrong = [ 1 "b" ]; }"#
+    );
+    assert!(eval_err("builtins.partition (x: x) [ 1 ]").contains("instead of a bool"));
+}
+
+#[test]
+fn flatten_recurses_and_keeps_scalars() {
+    assert_eq!(
+        eval(r#"builtins.concatLists [ [ 1 ] [ ] [ [ 2 ] 3 ] ]"#),
+        "[ 1 [ 2 ] 3 ]"
+    );
+    assert_eq!(
+        eval(r#"lib.flatten [ 1 [ 2 [ [ 3 ] ] ] [ ] "a" ]"#),
+        r#"[ 1 2 3 "a" ]"#
+    );
+    assert_eq!(eval("lib.flatten 1"), "[ 1 ]");
+}
+
+#[test]
+fn unique_and_subtract_lists_use_deep_equality() {
+    assert_eq!(
+        eval("lib.unique [ 3 1 { a = 1; } 3 2 { a = 1; } 1 ]"),
+        "[ 3 1 { a = 1; } 2 ]"
+    );
+    assert_eq!(
+        eval(r#"lib.subtractLists [ "b" [ 1 ] ] [ "a" "b" [ 1 ] "c" "b" ]"#),
+        r#"[ "a" "c" ]"#
+    );
+}
+
+#[test]
+fn name_value_pairs_round_trip_through_list_to_attrs() {
+    assert_eq!(
+        eval(r#"lib.nameValuePair "a" 1"#),
+        r#"{ name = "a"; value = 1; }"#
+    );
+    assert_eq!(
+        eval(r#"lib.listToAttrs (map (n: lib.nameValuePair n "${n}-value") [ "b" "a" ])"#),
+        r#"{ a = "a-value"; b = "b-value"; }"#
+    );
+    // First occurrence wins and values stay lazy
+    assert_eq!(
+        eval(
+            r#"let s = builtins.listToAttrs [ (lib.nameValuePair "a" 1) (lib.nameValuePair "a" (throw "x")) { name = "b"; value = throw "y"; } ]; in [ s.a (s ? b) ]"#
+        ),
+        "[ 1 true ]"
+    );
+    assert!(eval_err("builtins.listToAttrs [ { name = \"a\"; } ]").contains("value attribute"));
+}
+
+#[test]
+fn split_string_matches_nix_edge_cases() {
+    assert_eq!(
+        eval(r#"builtins.split "(a)|(c)" "xaybc""#),
+        r#"[ "x" [ "a" null ] "yb" [ null "c" ] "" ]"#
+    );
+    assert_eq!(
+        eval(r#"lib.splitString "." "a.b..c.""#),
+        r#"[ "a" "b" "" "c" "" ]"#
+    );
+    assert_eq!(
+        eval(r#"lib.splitString "" "abc""#),
+        r#"[ "" "a" "b" "c" "" ]"#
+    );
+    assert_eq!(eval(r#"lib.splitString "," """#), r#"[ "" ]"#);
+}
+
+#[test]
+fn escape_shell_arg_single_quotes() {
+    assert_eq!(
+        eval(r#"lib.escapeShellArg "it's here""#),
+        r#""'it'\\''s here'""#
+    );
+    assert_eq!(
+        eval(r#"lib.escapeShellArgs [ "a b" "c" ]"#),
+        r#""'a b' 'c'""#
+    );
+}
+
+#[test]
+fn attribute_order_is_sorted_and_consistent() {
+    let set = r#"{ b = 2; a = 1; "c d" = 3; C = 0; }"#;
+    assert_eq!(
+        eval(&format!("builtins.attrNames {}", set)),
+        r#"[ "C" "a" "b" "c d" ]"#
+    );
+    assert_eq!(eval(&format!("builtins.attrValues {}", set)), "[ 0 1 2 3 ]");
+    assert_eq!(
+        eval(&format!(
+            "builtins.attrValues (builtins.mapAttrs (n: v: n) {})",
+            set
+        )),
+        r#"[ "C" "a" "b" "c d" ]"#
+    );
+    assert_eq!(
+        eval(r#"builtins.toJSON { z = [ 1 null ]; a = { y = true; b = "x"; }; }"#),
+        r#""{\"a\":{\"b\":\"x\",\"y\":true},\"z\":[1,null]}""#
+    );
+}
+
+#[test]
+fn bitwise_operations_take_integers() {
+    assert_eq!(
+        eval("[ (builtins.bitAnd 12 10) (builtins.bitOr 12 10) (builtins.bitXor 12 10) (builtins.bitAnd (-1) 7) ]"),
+        "[ 8 14 6 7 ]"
+    );
+    assert!(eval_err("builtins.bitOr 1 2.0")
+        .contains("bitOr expects two integers, found int and float"));
+}
+
+#[test]
+fn ceil_and_floor_round_towards_infinities() {
+    assert_eq!(
+        eval("map builtins.floor [ 2.5 (-2.5) (-0.1) 3 ]"),
+        "[ 2 -3 -1 3 ]"
+    );
+    assert_eq!(
+        eval("map builtins.ceil [ 2.5 (-2.5) (-0.1) 3 ]"),
+        "[ 3 -2 0 3 ]"
+    );
+    assert_eq!(eval("builtins.floor (7 / 2.0)"), "3");
+    assert_eq!(
+        eval("[ (1 / 4.0) (-3 * 0.5) (1 - 0.25) ]"),
+        "[ 0.25 -1.5 0.75 ]"
+    );
+    assert!(eval_err("builtins.floor 1.0e30").contains("cannot convert"));
+    assert!(eval_err("builtins.floor 9223372036854775808.0").contains("cannot convert"));
+    assert_eq!(
+        eval("builtins.ceil (-9223372036854775808.0)"),
+        "-9223372036854775808"
+    );
+    assert!(eval_err("1.0 / 0").contains("division by zero"));
+}
+
+#[test]
+fn foldl_and_foldr_differ_in_direction_and_argument_order() {
+    let op = r#"(a: b: "(${a}${b})")"#;
+    assert_eq!(
+        eval(&format!(r#"lib.foldl {} "0" [ "1" "2" "3" ]"#, op)),
+        r#""(((01)2)3)""#
+    );
+    assert_eq!(
+        eval(&format!(r#"builtins.foldl' {} "0" [ "1" "2" "3" ]"#, op)),
+        r#""(((01)2)3)""#
+    );
+    assert_eq!(
+        eval(&format!(r#"lib.foldr {} "0" [ "1" "2" "3" ]"#, op)),
+        r#""(1(2(30)))""#
+    );
+    assert_eq!(
+        eval("lib.foldr (x: acc: [ x ] ++ acc) [ ] [ 1 2 ]"),
+        "[ 1 2 ]"
+    );
+    assert_eq!(eval("lib.foldl (acc: x: x - acc) 0 [ ]"), "0");
+}
+
+#[test]
+fn pipe_applies_functions_left_to_right() {
+    assert_eq!(
+        eval("lib.pipe 2 [ (x: x + 1) (x: x * 10) (lib.flip builtins.bitAnd 14) ]"),
+        "14"
+    );
+    assert_eq!(eval("lib.pipe 1 [ ]"), "1");
+    assert_eq!(eval("lib.flip (a: b: a - b) 1 10"), "9");
+    assert_eq!(
+        eval(r#"map (lib.const "x") [ 1 (throw "unused") ]"#),
+        r#"[ "x" "x" ]"#
+    );
+    assert!(eval_err("lib.pipe 1 [ (x: x) 2 ]").contains("found int at position 1"));
+}
+
+#[test]
+fn prefixes_and_suffixes() {
+    assert_eq!(
+        eval(
+            r#"[ (lib.hasPrefix "foo" "foobar") (lib.hasPrefix "bar" "foobar") (lib.hasSuffix "bar" "foobar") (lib.hasSuffix "" "x") ]"#
+        ),
+        "[ true false true true ]"
+    );
+    assert_eq!(
+        eval(r#"[ (lib.removePrefix "foo" "foobar") (lib.removeSuffix ".nix" "default.nix") ]"#),
+        r#"[ "bar" "default" ]"#
+    );
+    // Strings without the affix are passed through
+    assert_eq!(
+        eval(
+            r#"[ (lib.removePrefix "x" "foobar") (lib.removeSuffix "foo" "foobar") (lib.removePrefix "foobarbaz" "foobar") ]"#
+        ),
+        r#"[ "foobar" "foobar" "foobar" ]"#
+    );
+}
+
+#[test]
+fn case_conversion_and_int_parsing() {
+    assert_eq!(
+        eval(r#"[ (lib.toUpper "abc-é1") (lib.toLower "ÀBC-Ü") ]"#),
+        r#"[ "ABC-é1" "Àbc-Ü" ]"#
+    );
+    assert_eq!(
+        eval(r#"[ (lib.toInt "42") (lib.toInt " -7 ") (lib.toInt "0") (lib.toIntBase10 "0010") ]"#),
+        "[ 42 -7 0 10 ]"
+    );
+    assert!(eval_err(r#"lib.toInt "012""#).contains("between octal and zero padded integer"));
+    assert!(eval_err(r#"lib.toIntBase10 "1.5""#).contains(r#"Could not convert "1.5" to int."#));
+    assert!(eval_err(r#"lib.toInt "abc""#).contains("Could not convert"));
+}
+
+#[test]
+fn to_string_coerces_packages_and_scalars() {
+    assert_eq!(
+        eval(r#""${pkgs.hello}/bin/hello""#),
+        r#""/nix/store/00000000000000000000000000000000-hello/bin/hello""#
+    );
+    assert_eq!(
+        eval("builtins.toString pkgs.hello == \"${pkgs.hello}\""),
+        "true"
+    );
+    assert_eq!(
+        eval(r#"toString [ 1 true false null "a" [ 2.5 ] { __toString = self: "s"; } ]"#),
+        r#""1 1   a 2.500000 s""#
+    );
+    assert!(eval_err(r#""${1}""#).contains("cannot coerce int to a string"));
+}
+
+#[test]
+fn to_string_lists() {
+    assert_eq!(eval(r#"toString [ 1 "a" ./p ]"#), r#""1 a ./p""#);
+    assert_eq!(eval(r#"toString [ [ ] "a" [ 1 2 ] ]"#), r#""a 1 2""#);
+    let err = try_eval("toString [ 1 { } ]").unwrap_err();
+    assert_eq!(err.to_string(), "while coercing list element 1 to a string");
+    assert_eq!(
+        err.root_cause().to_string(),
+        "cannot coerce set to a string"
+    );
+}
+
+#[test]
+fn map_attrs_renamed_prefixes_keys() {
+    assert_eq!(
+        eval(r#"lib.mapAttrs' (n: v: lib.nameValuePair "prefix-${n}" (v * 2)) { a = 1; b = 2; }"#),
+        r#"{ prefix-a = 2; prefix-b = 4; }"#
+    );
+    // Attributes are visited in order, and the first one to claim a name keeps it
+    assert_eq!(
+        eval(r#"lib.mapAttrs' (n: v: lib.nameValuePair "same" n) { b = 1; a = 2; }"#),
+        r#"{ same = "a"; }"#
+    );
+}
+
+#[test]
+fn gen_attrs_matches_list_to_attrs() {
+    let names = r#"[ "b" "a" "b" ]"#;
+    let f = r#"(n: "${n}-value")"#;
+    assert_eq!(
+        eval(&format!("lib.genAttrs {} {}", names, f)),
+        r#"{ a = "a-value"; b = "b-value"; }"#
+    );
+    assert_eq!(
+        eval(&format!(
+            "lib.genAttrs {names} {f} == lib.listToAttrs (map (n: lib.nameValuePair n ({f} n)) {names})",
+            names = names,
+            f = f
+        )),
+        "true"
+    );
+    assert!(eval_err("lib.genAttrs [ 1 ] (n: n)").contains("expects a list of strings, found int"));
+}
+
+#[test]
+fn zip_attrs_groups_values_by_name() {
+    let sets = "[ { a = 1; b = 2; } { b = 3; c = 4; } { a = 5; b = 6; } ]";
+    assert_eq!(
+        eval(&format!("lib.zipAttrs {}", sets)),
+        "{ a = [ 1 5 ]; b = [ 2 3 6 ]; c = [ 4 ]; }"
+    );
+    assert_eq!(
+        eval(&format!(
+            r#"builtins.zipAttrsWith (n: vs: "${{n}}=${{toString (lib.foldl (x: y: x + y) 0 vs)}}") {}"#,
+            sets
+        )),
+        r#"{ a = "a=6"; b = "b=11"; c = "c=4"; }"#
+    );
+}
+
+#[test]
+fn sources_record_their_path_without_filtering() {
+    assert_eq!(
+        eval("builtins.path { path = ./src; name = \"demo-src\"; }"),
+        "«source ./src»"
+    );
+    // The filter is never called
+    let filtered = "builtins.filterSource (path: type: throw \"filtered\") ./.";
+    assert_eq!(eval(filtered), "«source ./. filtered by a function»");
+    assert_eq!(
+        eval(&format!("\"${{{}}}\"", filtered)),
+        "\"/nix/store/11111111111111111111111111111111-source\""
+    );
+}
+
+#[test]
+fn cleaned_sources_keep_the_original_path() {
+    assert_eq!(
+        eval("lib.cleanSource ./."),
+        "«source ./. filtered by cleanSource»"
+    );
+    assert_eq!(
+        eval("lib.cleanSource (lib.sourceByRegex ./pkg [ \"^src.*\" \".*\\\\.c\" ])"),
+        "«source ./pkg filtered by sourceByRegex [ \"^src.*\" \".*\\\\.c\" ], cleanSource»"
+    );
+    assert_eq!(
+        eval("\"${lib.cleanSource ./pkg}\""),
+        "\"/nix/store/11111111111111111111111111111111-pkg\""
+    );
+}
+
+#[test]
+fn concat_map_concatenates_results() {
+    assert_eq!(
+        eval("builtins.concatMap (x: [ x (x * 10) ]) [ 1 2 3 ]"),
+        "[ 1 10 2 20 3 30 ]"
+    );
+    assert_eq!(eval("lib.concatMap (x: [ ]) [ 1 2 ]"), "[ ]");
+    let err = eval_err("builtins.concatMap (x: if x == 2 then x else [ x ]) [ 1 2 3 ]");
+    assert!(err.contains("found int for element 1"));
+}
+
+#[test]
+fn any_and_all_short_circuit() {
+    let pred = "(x: if x == 3 then throw \"reached\" else x > 1)";
+    assert_eq!(eval(&format!("builtins.any {} [ 1 2 3 ]", pred)), "true");
+    assert_eq!(eval(&format!("builtins.all {} [ 2 1 3 ]", pred)), "false");
+    assert_eq!(eval("lib.any (x: x) [ ]"), "false");
+    assert_eq!(eval("lib.all (x: x) [ ]"), "true");
+    assert!(eval_err("builtins.any (x: x) [ 1 ]").contains("expected a bool, found int"));
+}
+
+#[test]
+fn group_by_buckets_by_key() {
+    let parity = "(x: if x / 2 * 2 == x then \"even\" else \"odd\")";
+    assert_eq!(
+        eval(&format!("lib.groupBy {} [ 1 2 3 4 5 ]", parity)),
+        "{ even = [ 2 4 ]; odd = [ 1 3 5 ]; }"
+    );
+    assert!(eval_err("builtins.groupBy (x: x) [ 1 ]").contains("to return a string, found int"));
+}
+
+#[test]
+fn intersect_attrs_takes_values_from_the_second_set() {
+    assert_eq!(
+        eval("builtins.intersectAttrs { a = 1; b = 2; } { b = 3; c = 4; }"),
+        "{ b = 3; }"
+    );
+    assert_eq!(
+        eval("builtins.intersectAttrs (builtins.functionArgs ({ zlib, openssl ? null }: 1)) pkgs"),
+        "{ openssl = «pkgs.openssl»; zlib = «pkgs.zlib»; }"
+    );
+    assert!(eval_err("builtins.intersectAttrs 1 { }").contains("cannot be treated as set"));
+}
+
+#[test]
+fn override_priorities_survive_merging() {
+    let tagged = "{ a = lib.mkDefault 1; b.c = lib.mkForce 2; }";
+    assert_eq!(
+        eval(&format!("({} // {{ d = 3; }}).a", tagged)),
+        "{ _type = \"override\"; content = 1; priority = 1000; }"
+    );
+    assert_eq!(
+        eval(&format!(
+            "(lib.recursiveUpdate {} {{ b.e = lib.mkOverride 10 4; }}).b",
+            tagged
+        )),
+        "{ c = { _type = \"override\"; content = 2; priority = 50; }; \
+         e = { _type = \"override\"; content = 4; priority = 10; }; }"
+    );
+}
+
+#[test]
+fn recursive_update_merges_nested_sets() {
+    assert_eq!(
+        eval("lib.recursiveUpdate { a.b = 1; a.c = 2; d = 1; } { a.c = 3; d.e = 4; }"),
+        "{ a = { b = 1; c = 3; }; d = { e = 4; }; }"
+    );
+}
+
+#[test]
+fn paths_join_with_strings() {
+    assert_eq!(eval("./. + \"/bar\""), "./bar");
+    // Like in Nix, the trailing slash is dropped before the second string is appended
+    assert_eq!(
+        eval("let dir = \"sub\"; in ./src + \"/${dir}/\" + \"file.c\""),
+        "./src/subfile.c"
+    );
+    assert_eq!(eval("\"${./foo}\""), "\"./foo\"");
+    assert_eq!(eval("\"prefix-\" + ./foo"), "\"prefix-./foo\"");
+    assert_eq!(
+        eval("let name = \"x\"; in ./patches/${name}.patch"),
+        "./patches/x.patch"
+    );
+}
+
+#[test]
+fn base_name_of_and_dir_of_match_nix() {
+    let base = |s: &str| eval(&format!("baseNameOf {}", s));
+    assert_eq!(base("\"/foo/bar\""), "\"bar\"");
+    assert_eq!(base("\"/foo/bar/\""), "\"bar\"");
+    assert_eq!(base("\"foo//\""), "\"\"");
+    assert_eq!(base("\"/\""), "\"\"");
+    assert_eq!(base("\"\""), "\"\"");
+    assert_eq!(base("./src/main.c"), "\"main.c\"");
+
+    let dir = |s: &str| eval(&format!("builtins.dirOf {}", s));
+    assert_eq!(dir("\"/foo/bar\""), "\"/foo\"");
+    assert_eq!(dir("\"/foo/bar/\""), "\"/foo/bar\"");
+    assert_eq!(dir("\"/foo\""), "\"/\"");
+    assert_eq!(dir("\"/\""), "\"/\"");
+    assert_eq!(dir("\"foo\""), "\".\"");
+    assert_eq!(dir("./src/main.c"), "./src");
+}
+
+#[test]
+fn placeholders_are_recognizable_stand_ins() {
+    assert_eq!(
+        eval("\"--prefix=${builtins.placeholder \"dev\"}\""),
+        "\"--prefix=/nix/store/22222222222222222222222222222222-output-dev\""
+    );
+    assert_eq!(
+        eval("map builtins.hasContext [ \"plain\" \"${pkgs.zlib}/lib\" (builtins.placeholder \"out\") ]"),
+        "[ false true true ]"
+    );
+    assert_eq!(
+        eval("builtins.unsafeDiscardStringContext \"${pkgs.zlib}\""),
+        "\"/nix/store/00000000000000000000000000000000-zlib\""
+    );
+    assert_eq!(
+        eval("let s = \"${pkgs.hello}/bin\"; in builtins.unsafeDiscardStringContext s == s"),
+        "true"
+    );
+    assert_eq!(
+        eval("let s = \"${pkgs.hello}\"; in builtins.unsafeDiscardStringContext s < s"),
+        "false"
+    );
+    assert_eq!(
+        eval("builtins.hasContext (builtins.unsafeDiscardStringContext \"${pkgs.zlib}/lib\")"),
+        "false"
+    );
+}
+
+#[test]
+fn discarding_context_keeps_the_string() {
+    let discarded = |expr: &str| {
+        eval(&format!(
+            "let s = builtins.unsafeDiscardStringContext \"${{pkgs.zlib}}/lib\"; in {}",
+            expr
+        ))
+    };
+    assert_eq!(
+        discarded("lib.hasPrefix \"/nix/store/00000000000000000000000000000000-zlib\" s"),
+        "true"
+    );
+    assert_eq!(
+        discarded(
+            "builtins.hashString \"md5\" s == builtins.hashString \"md5\" \"${pkgs.zlib}/lib\""
+        ),
+        "true"
+    );
+    assert_eq!(
+        discarded("builtins.toJSON s"),
+        eval("builtins.toJSON \"${pkgs.zlib}/lib\"")
+    );
+    assert_eq!(discarded("builtins.hasContext \"-L${s}\""), "false");
+    assert_eq!(
+        discarded("builtins.hasContext (s + \"${pkgs.zlib}\")"),
+        "true"
+    );
+}
+
+#[test]
+fn attr_by_path_and_set_attr_by_path() {
+    let set = "{ a = { b = { c = 1; }; d = 2; }; }";
+    let get = |path: &str| eval(&format!("lib.attrByPath {} \"default\" {}", path, set));
+    assert_eq!(get("[ \"a\" \"b\" \"c\" ]"), "1");
+    assert_eq!(get("[ \"a\" \"x\" \"c\" ]"), "\"default\"");
+    assert_eq!(get("[ \"a\" \"d\" \"c\" ]"), "\"default\"");
+    assert_eq!(get("[ ]"), "{ a = { b = { c = 1; }; d = 2; }; }");
+    assert_eq!(
+        eval("lib.setAttrByPath [ \"a\" \"b\" ] 3"),
+        "{ a = { b = 3; }; }"
+    );
+    assert_eq!(eval("lib.setAttrByPath [ ] 3"), "3");
+}
+
+#[test]
+fn has_attr_by_path_stops_at_non_sets() {
+    let has = |path: &str| {
+        eval(&format!(
+            "lib.hasAttrByPath {} {{ a = {{ b = \"str\"; }}; }}",
+            path
+        ))
+    };
+    assert_eq!(has("[ \"a\" \"b\" ]"), "true");
+    assert_eq!(has("[ \"a\" \"b\" \"c\" ]"), "false");
+    assert_eq!(has("[ \"x\" ]"), "false");
+    assert_eq!(has("[ ]"), "true");
+}
+
+#[test]
+fn count_and_indexed_maps() {
+    assert_eq!(eval("lib.count (x: x > 1) [ 1 2 3 ]"), "2");
+    assert_eq!(
+        eval("lib.imap0 (i: x: \"${toString i}-${x}\") [ \"a\" \"b\" ]"),
+        "[ \"0-a\" \"1-b\" ]"
+    );
+    assert_eq!(
+        eval("lib.imap1 (i: x: \"${toString i}-${x}\") [ \"a\" \"b\" ]"),
+        "[ \"1-a\" \"2-b\" ]"
+    );
+}
+
+#[test]
+fn escape_nix_string_quotes_for_nix() {
+    let escaped = try_eval(r#"lib.escapeNixString "a\"b\\c $d ${"$"}{e}""#)
+        .and_then(|s| s.try_into_string())
+        .unwrap();
+    assert_eq!(escaped, r#""a\"b\\c \$d \${e}""#);
+}
+
+#[test]
+fn mk_value_string_default_matches_nixpkgs() {
+    let value = |v: &str| eval(&format!("lib.generators.mkValueStringDefault {{ }} {}", v));
+    assert_eq!(value("1"), "\"1\"");
+    assert_eq!(value("1.5"), "\"1.500000\"");
+    assert_eq!(value("true"), "\"true\"");
+    assert_eq!(value("null"), "\"null\"");
+    assert_eq!(value("\"unquoted\""), "\"unquoted\"");
+    assert_eq!(
+        value("pkgs.hello"),
+        "\"/nix/store/00000000000000000000000000000000-hello\""
+    );
+    assert!(eval_err("lib.generators.mkValueStringDefault { } [ ]").contains("list"));
+}
+
+#[test]
+fn ini_generators_match_nixpkgs() {
+    assert_eq!(
+        eval(
+            r#"lib.generators.toINI { } { main = { name = "demo"; debug = true; }; "net[0]" = { port = 80; }; }"#
+        ),
+        r#""[main]\ndebug=true\nname=demo\n\n[net\\[0\\]]\nport=80\n""#
+    );
+    assert_eq!(
+        eval(r#"lib.generators.toKeyValue { listsAsDuplicateKeys = true; } { "a=b" = [ 1 2 ]; }"#),
+        r#""a\\=b=1\na\\=b=2\n""#
+    );
+    assert_eq!(
+        eval(
+            r#"lib.generators.toKeyValue { mkKeyValue = lib.generators.mkKeyValueDefault { } ": "; } { a = 1; }"#
+        ),
+        r#""a: 1\n""#
+    );
+    assert_eq!(
+        eval(
+            r#"lib.generators.toINIWithGlobalSection { } { globalSection = { top = 1; }; sections = { s = { x = 2; }; }; }"#
+        ),
+        r#""top=1\n\n[s]\nx=2\n""#
+    );
+}
+
+#[test]
+fn git_ini_writes_subsections() {
+    assert_eq!(
+        eval(
+            r#"lib.generators.toGitINI { user = { name = "A \"B\""; }; remote.origin = { url = "u"; fetch = [ "a" "b" ]; }; core.autocrlf = false; }"#
+        ),
+        r#""[core]\n\tautocrlf = false\n\n[remote \"origin\"]\n\tfetch = \"a\"\n\tfetch = \"b\"\n\turl = \"u\"\n\n[user]\n\tname = \"A \\\"B\\\"\"\n""#
+    );
+    assert!(eval_err("lib.generators.toGitINI { top = 1; }").contains("outside of a section"));
+}
+
+#[test]
+fn yaml_generator_writes_json() {
+    assert_eq!(
+        eval("lib.generators.toYAML { } { a = [ 1 null ]; }"),
+        r#""{\"a\":[1,null]}""#
+    );
+}
+
+#[test]
+fn from_toml_parses_tables() {
+    assert_eq!(
+        eval(
+            r#"builtins.fromTOML "name = \"demo\"\nversion = 2\n[features]\ndefault = [ \"std\" ]\nratio = 0.5\n""#
+        ),
+        r#"{ features = { default = [ "std" ]; ratio = 0.5; }; name = "demo"; version = 2; }"#
+    );
+    assert!(eval_err(r#"builtins.fromTOML "a = ""#).contains("while parsing TOML"));
+    assert!(eval_err(r#"builtins.fromTOML "a = 1979-05-27""#).contains("dates and times"));
+}
+
+#[test]
+fn platform_groups_list_systems() {
+    assert_eq!(
+        eval("lib.platforms.darwin"),
+        r#"[ "aarch64-darwin" "x86_64-darwin" ]"#
+    );
+    assert_eq!(eval("lib.systems.doubles.riscv"), r#"[ "riscv64-linux" ]"#);
+    assert_eq!(
+        eval(
+            r#"builtins.any (s: s == "x86_64-linux") (lib.platforms.linux ++ lib.platforms.darwin)"#
+        ),
+        "true"
+    );
+    assert_eq!(eval_err("lib.platforms.plan9"), "attribute 'plan9' missing");
+}
+
+#[test]
+fn hashes_are_computed() {
+    let hash = |algo: &str| eval(&format!("builtins.hashString \"{}\" \"hello\"", algo));
+    assert_eq!(hash("md5"), r#""5d41402abc4b2a76b9719d911017c592""#);
+    assert_eq!(
+        hash("sha1"),
+        r#""aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d""#
+    );
+    assert_eq!(
+        hash("sha256"),
+        r#""2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824""#
+    );
+    assert!(eval_err(r#"builtins.hashString "crc32" "hello""#)
+        .contains("expected one of md5, sha1, sha256, sha512"));
+
+    let path = std::env::temp_dir().join(format!("rnix-experiments-hash-{}", std::process::id()));
+    std::fs::write(&path, "hello").unwrap();
+    assert_eq!(
+        eval(&format!("builtins.hashFile \"md5\" {}", path.display())),
+        r#""5d41402abc4b2a76b9719d911017c592""#
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn versions_compare_like_nix() {
+    // From the versions test of Nix itself
+    let cases = [
+        ("1.0", "2.3", -1),
+        ("2.1", "2.3", -1),
+        ("2.3", "2.3", 0),
+        ("2.5", "2.3", 1),
+        ("3.1", "2.3", 1),
+        ("2.3.1", "2.3", 1),
+        ("2.3.1", "2.3a", 1),
+        ("2.3pre1", "2.3", -1),
+        ("2.3pre3", "2.3pre12", -1),
+        ("2.3a", "2.3c", -1),
+        ("2.3pre1", "2.3c", -1),
+        ("2.3pre1", "2.3q", -1),
+        ("2.0", "2.0.0", -1),
+        ("1.0pre", "1.0", -1),
+        ("1.0-rc1", "1.0.rc1", 0),
+    ];
+    for (v1, v2, expected) in cases {
+        assert_eq!(
+            eval(&format!("builtins.compareVersions \"{}\" \"{}\"", v1, v2)),
+            expected.to_string(),
+            "comparing {} with {}",
+            v1,
+            v2
+        );
+    }
+    assert_eq!(
+        eval(r#"builtins.splitVersion "1.2.3pre-rc1""#),
+        r#"[ "1" "2" "3" "pre" "rc" "1" ]"#
+    );
+    assert_eq!(
+        eval(r#"[ (lib.versionOlder "1.9" "1.10") (lib.versionAtLeast "2.0" "2.0") ]"#),
+        "[ true true ]"
+    );
+}
+
+#[test]
+fn drv_names_split_like_nix() {
+    let parse = |s: &str| eval(&format!("builtins.parseDrvName \"{}\"", s));
+    assert_eq!(
+        parse("nix-0.12pre12876"),
+        r#"{ name = "nix"; version = "0.12pre12876"; }"#
+    );
+    assert_eq!(
+        parse("firefox-esr-115.0-2"),
+        r#"{ name = "firefox-esr"; version = "115.0-2"; }"#
+    );
+    assert_eq!(
+        parse("font-util-_1"),
+        r#"{ name = "font-util"; version = "_1"; }"#
+    );
+    assert_eq!(parse("hello"), r#"{ name = "hello"; version = ""; }"#);
+    assert_eq!(
+        parse("trailing-"),
+        r#"{ name = "trailing-"; version = ""; }"#
+    );
+
+    assert_eq!(
+        eval(r#"[ (lib.getName "cargo-edit-0.12.2") (lib.getVersion "cargo-edit-0.12.2") ]"#),
+        r#"[ "cargo-edit" "0.12.2" ]"#
+    );
+    assert_eq!(
+        eval(
+            r#"let drv = { name = "hello-2.12"; }; in [ (lib.getName drv) (lib.getVersion drv) ]"#
+        ),
+        r#"[ "hello" "2.12" ]"#
+    );
+    assert_eq!(
+        eval(r#"lib.getVersion { name = "python3.11-requests-2.31"; version = "2.31.0"; }"#),
+        r#""2.31.0""#
+    );
+    assert_eq!(eval("lib.getName pkgs.hello"), r#""hello""#);
+}
+
+#[test]
+fn map_attrs_recursive() {
+    let config =
+        r#"{ name = "app"; server = { host = "localhost"; port = "80"; tls.cert = "a.pem"; }; }"#;
+    assert_eq!(
+        eval(&format!(
+            "lib.mapAttrsRecursive (path: lib.toUpper) {}",
+            config
+        )),
+        r#"{ name = "APP"; server = { host = "LOCALHOST"; port = "80"; tls = { cert = "A.PEM"; }; }; }"#
+    );
+    assert_eq!(
+        eval(&format!("lib.mapAttrsRecursive (path: v: path) {}", config)),
+        r#"{ name = [ "name" ]; server = { host = [ "server" "host" ]; port = [ "server" "port" ]; tls = { cert = [ "server" "tls" "cert" ]; }; }; }"#
+    );
+    // Sets the condition rejects are passed to the function whole
+    assert_eq!(
+        eval(&format!(
+            "(lib.mapAttrsRecursiveCond (s: !(s ? cert)) (path: v: path) {}).server.tls",
+            config
+        )),
+        r#"[ "server" "tls" ]"#
+    );
+}
+
+#[test]
+fn cartesian_products() {
+    assert_eq!(
+        eval("lib.cartesianProductOfSets { os = [ \"linux\" \"darwin\" ]; arch = [ \"x86_64\" \"aarch64\" ]; }"),
+        r#"[ { arch = "x86_64"; os = "linux"; } { arch = "x86_64"; os = "darwin"; } { arch = "aarch64"; os = "linux"; } { arch = "aarch64"; os = "darwin"; } ]"#
+    );
+    assert_eq!(
+        eval("lib.cartesianProductOfSets { a = [ 1 2 ]; b = [ ]; }"),
+        "[ ]"
+    );
+    assert_eq!(eval("lib.cartesianProduct { }"), "[ { } ]");
+    assert_eq!(
+        eval("lib.crossLists (a: b: a + b) [ [ 10 20 ] [ 1 2 ] ]"),
+        "[ 11 12 21 22 ]"
+    );
+}
+
+#[test]
+fn characters() {
+    assert_eq!(
+        eval(r#"lib.stringToCharacters "héllo""#),
+        r#"[ "h" "é" "l" "l" "o" ]"#
+    );
+    assert_eq!(eval(r#"lib.stringToCharacters """#), "[ ]");
+    assert_eq!(
+        eval(r#"map lib.charToInt [ "a" " " "é" ]"#),
+        "[ 97 32 233 ]"
+    );
+    assert!(eval_err(r#"lib.charToInt "ab""#)
+        .contains(r#"charToInt expects a single character, found "ab""#));
+}
+
+#[test]
+fn ranges_and_list_ends() {
+    assert_eq!(eval("lib.range 2 5"), "[ 2 3 4 5 ]");
+    assert_eq!(eval("lib.range 3 3"), "[ 3 ]");
+    assert_eq!(eval("lib.range 5 2"), "[ ]");
+    assert_eq!(eval("lib.last [ 1 2 3 ]"), "3");
+    assert_eq!(eval("lib.last [ 1 ]"), "1");
+    assert_eq!(eval("lib.init [ 1 2 3 ]"), "[ 1 2 ]");
+    assert_eq!(eval("lib.init [ 1 ]"), "[ ]");
+    assert!(eval_err("lib.last [ ]").contains("lists.last: list must not be empty!"));
+    assert!(eval_err("lib.init [ ]").contains("lists.init: list must not be empty!"));
+}
+
+#[test]
+fn paths() {
+    assert_eq!(
+        eval(r#"map builtins.isPath [ ./a /etc/hosts "./a" (./a + "/b") ]"#),
+        "[ true true false true ]"
+    );
+    assert_eq!(eval("./a == ./a"), "true");
+    assert_eq!(eval(r#"./a == "./a""#), "false");
+    assert_eq!(eval(r#"./a != "./a""#), "true");
+    assert_eq!(eval("./a/../b == ./b"), "true");
+    assert_eq!(eval("./a/./b == ./a/b"), "true");
+    assert_eq!(eval("./a == ./b"), "false");
+    assert_eq!(eval(r#"./a + "/../b""#), "./b");
+}
+
+#[test]
+fn zipped_lists() {
+    assert_eq!(
+        eval("lib.zipListsWith (a: b: a + b) [ 1 2 3 ] [ 10 20 ]"),
+        "[ 11 22 ]"
+    );
+    assert_eq!(
+        eval(r#"lib.zipListsWith (a: b: a + b) [ "a" ] [ "b" "c" "d" ]"#),
+        r#"[ "ab" ]"#
+    );
+    assert_eq!(eval("lib.zipListsWith (a: b: a) [ ] [ 1 ]"), "[ ]");
+    assert_eq!(
+        eval("lib.zipLists [ 1 2 3 ] [ \"a\" \"b\" ]"),
+        r#"[ { fst = 1; snd = "a"; } { fst = 2; snd = "b"; } ]"#
+    );
+}
+
+#[test]
+fn function_args() {
+    assert_eq!(
+        eval("builtins.functionArgs ({ a, b ? 1 }: a)"),
+        "{ a = false; b = true; }"
+    );
+    assert_eq!(
+        eval("lib.functionArgs ({ a, ... }@args: args)"),
+        "{ a = false; }"
+    );
+    assert_eq!(eval("builtins.functionArgs (x: x)"), "{ }");
+    assert_eq!(
+        eval_err("builtins.functionArgs 1"),
+        "functionArgs expects a function, found int"
+    );
+}
+
+#[test]
+fn cat_attrs() {
+    assert_eq!(
+        eval("builtins.catAttrs \"a\" [ { a = 1; } { b = 0; } { a = 2; } ]"),
+        "[ 1 2 ]"
+    );
+    assert_eq!(eval("lib.catAttrs \"a\" [ { b = 0; } ]"), "[ ]");
+    assert_eq!(
+        eval_err("builtins.catAttrs \"a\" [ { a = 1; } 2 ]"),
+        "value of type int cannot be treated as set"
+    );
+}
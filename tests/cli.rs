@@ -0,0 +1,451 @@
+mod common;
+use common::run;
+
+#[test]
+fn dump_ast_only_skips_evaluation() {
+    let output = run(&["--dump-ast-only"], "throw \"not evaluated\"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("NODE_ROOT@"));
+    assert!(stdout.contains("TOKEN_STRING_CONTENT"));
+}
+
+#[test]
+fn dump_ast_prints_the_tree_before_the_result() {
+    let output = run(&["--dump-ast"], "{ a = 1; }");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("NODE_ROOT@"));
+    assert!(stdout.ends_with("\n{ a = 1; }\n"));
+}
+
+#[test]
+fn verbose_diagnostics_go_to_stderr() {
+    let quiet = run(&[], "{ a = { b = 1; }.b; }");
+    assert!(quiet.stderr.is_empty());
+
+    let output = run(&["-v"], "{ a = { b = 1; }.b; }");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "{ a = 1; }\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("debug: evaluating select { b = 1; }.b"));
+    assert!(!stderr.contains("NODE_SELECT"));
+
+    let output = run(&["-vv"], "{ a = { b = 1; }.b; }");
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("NODE_SELECT"));
+}
+
+#[test]
+fn diff_deps_lists_added_and_removed_packages() {
+    let body = "stdenv.mkDerivation (finalAttrs: {
+      pname = \"demo\";
+      buildInputs = [ pkgs.zlib ]
+        ++ lib.optionals stdenv.isLinux [ pkgs.systemd pkgs.libcap ]
+        ++ lib.optional stdenv.isDarwin pkgs.libiconv;
+      passthru.self = finalAttrs;
+    })";
+    let output = run(
+        &[
+            "diff-deps",
+            "--system",
+            "x86_64-linux",
+            "--system",
+            "aarch64-darwin",
+        ],
+        &format!("let stdenv = pkgs.stdenv; in {}", body),
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "+ libiconv\n- libcap\n- systemd\n"
+    );
+}
+
+#[test]
+fn graph_labels_edges_by_input_kind() {
+    let output = run(
+        &["--graph"],
+        r#"pkgs.stdenv.mkDerivation (finalAttrs: {
+          pname = "demo";
+          nativeBuildInputs = [ pkgs.pkg-config pkgs.zlib ];
+          buildInputs = [ pkgs.zlib ];
+          propagatedBuildInputs = [ pkgs.glib ];
+          postInstall = "${pkgs.coreutils}/bin/true";
+          # Refers back to the package itself
+          passthru.tests = finalAttrs;
+        })"#,
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        r#"digraph "demo" {
+  "demo" -> "coreutils" [label="other"];
+  "demo" -> "glib" [label="propagated"];
+  "demo" -> "pkg-config" [label="native"];
+  "demo" -> "zlib" [label="build"];
+  "demo" -> "zlib" [label="native"];
+}
+"#
+    );
+}
+
+#[test]
+fn check_reports_the_first_error_without_the_value() {
+    let output = run(&["check"], "{\n  a = 1;\n  b = lib.nope;\n}");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with(": failed\n"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: attribute 'nope' missing\n  while evaluating the value defined at line 4, column 7\n"
+    );
+
+    let body = "{ options.a = lib.mkOption { }; config.b = config.c; }";
+    let output = run(&["check", "--lenient"], body);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with(": ok, 1 warning\n"));
+}
+
+#[test]
+fn json_report_combines_options_dependencies_and_warnings() {
+    let body = "{
+  options.demo.enable = lib.mkEnableOption \"demo\";
+  config.pkg = {
+    buildInputs = lib.optional config.demo.enable pkgs.zlib ++ [ pkgs.openssl ];
+    port = config.other.port;
+  };
+}";
+    let output = run(&["--report", "json"], body);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "options": [
+                { "path": "demo.enable", "line": 5, "column": 32 },
+                { "path": "other.port", "line": 6, "column": 12 },
+            ],
+            "dependencies": ["openssl"],
+            "warnings": [
+                {
+                    "message": "access to undeclared option 'other'",
+                    "line": 6,
+                    "column": 12,
+                },
+            ],
+            "unknowns": [],
+            "files": [],
+            "availability": null,
+            "meta": null,
+        })
+    );
+}
+
+#[test]
+fn attr_selects_from_the_result() {
+    let source = r#"{ meta = { license = "mit"; "a.b" = 1; }; }"#;
+    let output = run(&["--attr", "meta.license"], source);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "\"mit\"\n");
+
+    let output = run(&["-A", "meta.\"a.b\""], source);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+
+    let output = run(&["--attr", "meta.homepage"], source);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("while selecting the attribute path 'meta.homepage'"));
+    assert!(stderr.contains("attribute 'homepage' missing"));
+}
+
+#[test]
+fn strict_deps_fails_on_unknown_inputs() {
+    let body = "pkgs.stdenv.mkDerivation {
+  name = \"demo\";
+  buildInputs = [ pkgs.zlib (builtins.getEnv \"EXTRA\") ];
+}";
+    let output = run(&["--strict-deps"], body);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: unresolved dependencies:\n  builtins.getEnv \"EXTRA\" at line 4, column 29\n"
+    );
+
+    let output = run(
+        &["--strict-deps"],
+        &body.replace("(builtins.getEnv \"EXTRA\")", ""),
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn watch_reevaluates_when_the_file_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    let path =
+        std::env::temp_dir().join(format!("rnix-experiments-watch-{}.nix", std::process::id()));
+    std::fs::write(&path, "{ a = 1; }").unwrap();
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .arg("--watch")
+        .arg(&path)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let next_line = || rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(next_line(), "{ a = 1; }");
+    // Give the watcher time to start before changing the file
+    std::thread::sleep(Duration::from_millis(500));
+    std::fs::write(&path, "{ a = 2; }").unwrap();
+    assert_eq!(next_line(), "---");
+    assert_eq!(next_line(), "{ a = 2; }");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn watch_reevaluates_when_an_imported_file_changes() {
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join(format!(
+        "rnix-experiments-watch-import-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("default.nix"), "{ b = import ./lib/b.nix; }").unwrap();
+    std::fs::write(dir.join("lib/b.nix"), "1").unwrap();
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .arg("--watch")
+        .arg(&dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let next_line = || rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(next_line(), "{ b = 1; }");
+    std::thread::sleep(Duration::from_millis(500));
+    std::fs::write(dir.join("lib/b.nix"), "2").unwrap();
+    assert_eq!(next_line(), "---");
+    assert_eq!(next_line(), "{ b = 2; }");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn directories_evaluate_their_default_nix() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .arg(fixtures.join("package_dir"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "{{ greeting = \"hello world\"; src = {}/package_dir/src; }}\n",
+            fixtures.display()
+        )
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .arg(&fixtures)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!(
+        "directory {} does not contain a default.nix",
+        fixtures.display()
+    )));
+}
+
+#[test]
+fn lenient_mode_collects_errors_by_kind() {
+    let body = "{\n  a = \"${1}\";\n  b = builtins.fooBar;\n  c = [ 1 (2 + \"x\") ];\n  ok = 1;\n}";
+    let output = run(&["--lenient"], body);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "{ a = «error»; b = «error»; c = [ 1 «error» ]; ok = 1; }\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        r#"error: 3 errors found
+type error (2)
+  at line 3, column 7: cannot coerce int to a string
+  3 |   a = "${1}";
+    |       ^
+  at line 5, column 11: cannot apply Add to int and string
+  5 |   c = [ 1 (2 + "x") ];
+    |           ^
+unknown builtin (1)
+  at line 4, column 7: attribute 'fooBar' missing
+  4 |   b = builtins.fooBar;
+    |       ^
+"#
+    );
+
+    let output = run(&["--lenient", "--max-errors", "1"], body);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: 3 errors found\ntype error (1)\n"));
+    assert!(stderr.ends_with("... and 2 more\n"));
+
+    // Without --lenient the first error still stops the evaluation
+    let output = run(&[], body);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn errors_can_be_printed_as_json() {
+    let body = "{\n  a = \"${1}\";\n  b = builtins.fooBar;\n}";
+    let output = run(&["--error-format", "json"], body);
+    assert!(!output.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    let file = error["span"]["file"].as_str().unwrap().to_owned();
+    assert_eq!(
+        error,
+        serde_json::json!({
+            "kind": "TypeError",
+            "message": "cannot coerce int to a string",
+            "span": { "file": file, "line": 3, "column": 7, "range": [36, 42] },
+        })
+    );
+
+    let output = run(&["--lenient", "--error-format", "json"], body);
+    let errors: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(errors[0]["kind"], "TypeError");
+    assert_eq!(errors[1]["kind"], "UnknownBuiltin");
+    assert_eq!(errors[1]["span"]["line"], 4);
+    assert_eq!(errors[1]["span"]["range"], serde_json::json!([50, 65]));
+
+    let output = run(&["--error-format", "json"], "{ a = ; }");
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "ParseError");
+    assert_eq!(error["span"]["line"], 2);
+    assert_eq!(error["span"]["column"], 7);
+
+    let path =
+        std::env::temp_dir().join(format!("rnix-experiments-args-{}.nix", std::process::id()));
+    std::fs::write(&path, "{ lib,\n  hello }:\nhello").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .args(["--error-format", "json"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["kind"], "UnknownRootArgument");
+    assert_eq!(error["message"], "unknown callPackage arg: hello");
+    assert_eq!(error["span"]["line"], 2);
+    assert_eq!(error["span"]["range"], serde_json::json!([9, 14]));
+}
+
+#[test]
+fn root_arguments_can_be_declared() {
+    let package = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/root_args");
+    let eval = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+            .args(args)
+            .arg(&package)
+            .output()
+            .unwrap()
+    };
+
+    let output = eval(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unknown callPackage arg: hello"));
+
+    let output = eval(&["--arg", "hello=pkg", "--arg", "myInput=set"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[ «pkgs.hello» { } ]\n"
+    );
+
+    let output = eval(&["--arg", "hello=pkg", "--lenient"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[ «pkgs.hello» «unknown myInput» ]\n"
+    );
+
+    let output = eval(&["--arg", "hello"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("expected NAME=KIND, found 'hello'"));
+
+    let output = eval(&["--arg", "hello=bogus"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "unknown argument kind 'bogus', expected one of lib, pkgs, config, pkg, set, unknown"
+    ));
+}
+
+#[test]
+fn config_values_are_read_from_json() {
+    let config = std::env::temp_dir().join(format!(
+        "rnix-experiments-config-{}.json",
+        std::process::id()
+    ));
+    std::fs::write(&config, r#"{ "port": 8080, "extra": "set" }"#).unwrap();
+    let body = "{ options.port = lib.mkOption { default = 80; }; config.x = [ config.port config.extra ]; }";
+    let output = run(
+        &["-A", "config.x", "--config", config.to_str().unwrap()],
+        body,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[ 8080 \"set\" ]\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "warning: access to undeclared option 'extra'\n"
+    );
+
+    std::fs::write(&config, "{ port = 1; }").unwrap();
+    let output = run(&["--config", config.to_str().unwrap()], body);
+    std::fs::remove_file(&config).unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("key must be a string"));
+}
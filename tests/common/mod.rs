@@ -0,0 +1,51 @@
+// Each test crate only uses some of these helpers
+#![allow(dead_code)]
+
+use rnix_experiments::{eval_str, print_value, EvalOptions, NixObject};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+// Run the evaluator with `args` on `body` wrapped in a `{ lib, pkgs, config, ... }:`
+// function
+pub fn run(args: &[&str], body: &str) -> Output {
+    let path = std::env::temp_dir().join(format!(
+        "rnix-experiments-{}-{}.nix",
+        std::process::id(),
+        NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, source(body)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rnix-experiments"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+// Wrap `body` in the same function as `run`
+fn source(body: &str) -> String {
+    format!("{{ lib, pkgs, config, ... }}:\n{}", body)
+}
+
+// Evaluate `body` in-process, returning the result or the error
+pub fn try_eval(body: &str) -> color_eyre::Result<NixObject> {
+    eval_str(&source(body), &EvalOptions::default())
+}
+
+// Evaluate `body`, returning the printed result
+pub fn eval(body: &str) -> String {
+    try_eval(body)
+        .and_then(|val| print_value(&val))
+        .unwrap_or_else(|e| panic!("evaluating {} failed: {:?}", body, e))
+}
+
+// Evaluate `body`, which is expected to fail, returning the error message
+pub fn eval_err(body: &str) -> String {
+    match try_eval(body).and_then(|val| print_value(&val)) {
+        Ok(val) => panic!("evaluating {} succeeded with {}", body, val),
+        Err(e) => e.to_string(),
+    }
+}
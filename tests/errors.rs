@@ -0,0 +1,36 @@
+mod common;
+use common::{eval, eval_err, try_eval};
+use rnix_experiments::EvalError;
+
+#[test]
+fn error_messages_are_kept() {
+    assert!(eval_err("undefinedName").contains("value not in scope: undefinedName"));
+    assert!(eval_err("{ a = 1; }.b").contains("attribute 'b' missing"));
+    assert!(eval_err("assert 1 == 2; null").contains("assertion '1 == 2' failed"));
+    assert!(eval_err("1 / 0").contains("division by zero"));
+    assert!(eval_err("({ a }: a) { }").contains("without required argument 'a'"));
+    assert!(eval_err(r#"builtins.abort "no""#)
+        .contains("evaluation aborted with the following error message: 'no'"));
+    assert!(eval_err("let x = x; in x").contains("infinite recursion encountered"));
+}
+
+#[test]
+fn error_kinds_can_be_matched() {
+    let kind = |body| try_eval(body).unwrap_err().downcast::<EvalError>().unwrap();
+    assert_eq!(kind("nope"), EvalError::NotInScope("nope".to_owned()));
+    assert_eq!(kind(r#"throw "x""#), EvalError::Throw("x".to_owned()));
+    assert_eq!(
+        kind("assert false; 1"),
+        EvalError::AssertFailure("false".to_owned())
+    );
+    assert!(matches!(kind(r#"1 + "a""#), EvalError::TypeError(_)));
+}
+
+#[test]
+fn error_context_is_added_to_failures() {
+    let body = r#"builtins.addErrorContext "while checking the version" (throw "bad version")"#;
+    let err = try_eval(body).unwrap_err();
+    assert_eq!(err.to_string(), "while checking the version");
+    assert_eq!(err.root_cause().to_string(), "bad version");
+    assert_eq!(eval(r#"builtins.addErrorContext (throw "unused") 1"#), "1");
+}
@@ -0,0 +1,7 @@
+rec {
+  a.b.c = 1;
+  d = a.b.c + 1;
+  "quoted attr" = d;
+  ${"dyn" + "amic"} = true;
+  inherit (a) b;
+}
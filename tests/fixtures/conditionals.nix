@@ -0,0 +1,5 @@
+let x = 5; in {
+  branch = if x > 3 then "big" else "small";
+  asserted = assert x == 5; x;
+  implication = false -> throw "not evaluated";
+}
@@ -0,0 +1 @@
+let x = 1; in assert x == 2; notInScope
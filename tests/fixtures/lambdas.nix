@@ -0,0 +1,4 @@
+let
+  add = a: b: a + b;
+  greet = { name, greeting ? "hello", ... }@args: "${greeting} ${name}${if args ? extra then "!" else ""}";
+in [ (add 1 2) (greet { name = "nix"; }) (greet { name = "x"; greeting = "hi"; extra = 1; }) ]
@@ -0,0 +1,5 @@
+let
+  x = 1;
+  y = x + 1;
+  inherit ({ z = 3; }) z;
+in [ x y z ]
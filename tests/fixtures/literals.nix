@@ -0,0 +1 @@
+[ 1 2.5 "str" null true false https://example.org ]
@@ -0,0 +1,7 @@
+let
+  outer = { a = "outer"; b = "outer"; };
+  inner = { a = "inner"; };
+in
+with outer;
+with inner;
+[ a b (let b = "let"; in with outer; b) (with { inner = 1; }; inner) ]
@@ -0,0 +1,7 @@
+{
+  arith = [ (1 + 2 * 3) (7 / 2) (7.0 / 2) (-4) (5 - 8) ];
+  logic = [ (!true) (true && false) (true || false) (1 < 2) ("a" != "b") ];
+  lists = [ 1 ] ++ [ 2 3 ];
+  update = { a = 1; b = 1; } // { b = 2; };
+  paren = (1 + 2) * 3;
+}
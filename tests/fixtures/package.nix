@@ -0,0 +1,7 @@
+{ lib, stdenv, pkgs, ... }:
+stdenv.mkDerivation {
+  pname = "demo";
+  version = "1.0";
+  src = lib.cleanSource ./.;
+  buildInputs = [ pkgs.zlib ];
+}
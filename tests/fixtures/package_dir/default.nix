@@ -0,0 +1,8 @@
+{ lib, ... }:
+let
+  helper = import ./helper.nix;
+in
+{
+  greeting = helper.greet "world";
+  src = ./src;
+}
@@ -0,0 +1,8 @@
+let a = "outer"; in rec {
+  a = "inner";
+  nested = { inherit a; };
+  set = { x = 1; };
+  inherit (set) x;
+  plain = let b = a; in { inherit b; };
+  outer = { inherit ({ inherit a; }) a; };
+}
@@ -0,0 +1,2 @@
+{ lib, hello, myInput }:
+[ hello myInput ]
@@ -0,0 +1,7 @@
+let name = "world"; in {
+  interpolated = "hello ${name}";
+  indented = ''
+    first
+      second ${toString 3}
+  '';
+}
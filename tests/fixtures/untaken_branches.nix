@@ -0,0 +1,6 @@
+let linux = true; in {
+  platform = if linux then "linux" else darwinOnlyPackage.out;
+  nested = if !linux then notInScope else if linux then 2 else throw "not evaluated";
+  asserted = assert linux; if linux then [ 1 ] else notInScope;
+  lazy = let skipped = if linux then unavailable else 1; in "unused";
+}
@@ -0,0 +1 @@
+let attrs = { a = 1; b = 2; }; in with attrs; [ a b (attrs ? a) (attrs ? c) (attrs.c or 3) ]
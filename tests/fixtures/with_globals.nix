@@ -0,0 +1,8 @@
+{ pkgs, ... }:
+with pkgs;
+[
+  (if true then hello else null)
+  (toString 1)
+  (map (x: x + 1) [ 1 ])
+  (with { true = false; null = 1; }; [ true null ])
+]
@@ -0,0 +1,121 @@
+mod common;
+use common::try_eval;
+use rnix_experiments::{eval_str, print_value, EvalOptions};
+
+fn generated(format: &str, value: &str) -> String {
+    try_eval(&format!(
+        "((pkgs.formats.{} {{ }}).generate \"out\" ({})).text",
+        format, value
+    ))
+    .and_then(|text| text.try_into_string())
+    .unwrap()
+}
+
+const NESTED: &str = r#"{
+  zeta = { enable = true; port = 8080; };
+  alpha = [ "b" "a" { y = null; x = 1.5; } ];
+  "with space" = "quoted \"value\"";
+}"#;
+
+#[test]
+fn json_output_is_sorted_and_pretty_printed() {
+    assert_eq!(
+        generated("json", NESTED),
+        r#"{
+  "alpha": [
+    "b",
+    "a",
+    {
+      "x": 1.5,
+      "y": null
+    }
+  ],
+  "with space": "quoted \"value\"",
+  "zeta": {
+    "enable": true,
+    "port": 8080
+  }
+}
+"#
+    );
+}
+
+#[test]
+fn to_json_matches_regardless_of_definition_order() {
+    let sorted = try_eval("builtins.toJSON { a.b = 1; a.c = 2; d = 3; }").unwrap();
+    let reversed = try_eval("builtins.toJSON { d = 3; a.c = 2; a.b = 1; }").unwrap();
+    assert_eq!(
+        sorted.try_into_string().unwrap(),
+        reversed.try_into_string().unwrap()
+    );
+}
+
+#[test]
+fn ini_sections_and_keys_are_sorted() {
+    assert_eq!(
+        generated("ini", "{ b = { z = 1; y = true; }; a = { x = \"s\"; }; }"),
+        "[a]\nx=s\n\n[b]\ny=true\nz=1\n"
+    );
+    assert_eq!(generated("keyValue", "{ b = 2; a = 1; }"), "a=1\nb=2\n");
+}
+
+#[test]
+fn merged_config_settings_generate_toml() {
+    let module = r#"{ config, lib, pkgs, ... }:
+let
+  format = pkgs.formats.toml { };
+  defaults = { log = "info"; server = { host = "localhost"; port = 80; }; };
+in {
+  options.services.foo.settings = {
+    log = lib.mkOption { type = lib.types.str; default = "warn"; };
+    workers = lib.mkOption { type = lib.types.int; default = 4; };
+  };
+  config.file = format.generate "foo.toml" (defaults // config.services.foo.settings);
+}"#;
+    let options = EvalOptions {
+        config: Some(
+            serde_json::json!({ "services": { "foo": { "settings": { "log": "debug" } } } }),
+        ),
+        ..Default::default()
+    };
+    let file = eval_str(module, &options)
+        .and_then(|module| module.try_into_set())
+        .map(|module| module.lookup("config").unwrap())
+        .and_then(|config| config.try_into_set())
+        .map(|config| config.lookup("file").unwrap())
+        .and_then(|file| file.try_into_set())
+        .unwrap();
+    assert_eq!(
+        file.lookup("text").unwrap().try_into_string().unwrap(),
+        "log = \"debug\"\nworkers = 4\n\n[server]\nhost = \"localhost\"\nport = 80\n"
+    );
+}
+
+#[test]
+fn numbers_are_formatted_like_nix() {
+    let formatted = |expr: &str| {
+        try_eval(&format!(
+            "map (n: [ (toString n) (builtins.toJSON n) ]) {}",
+            expr
+        ))
+        .and_then(|val| print_value(&val))
+        .unwrap()
+    };
+    assert_eq!(
+        formatted("[ 1.0 1.5 1.0e10 ]"),
+        r#"[ [ "1.000000" "1.0" ] [ "1.500000" "1.5" ] [ "10000000000.000000" "10000000000.0" ] ]"#
+    );
+    assert_eq!(
+        formatted("[ 9223372036854775807 (-42) ]"),
+        r#"[ [ "9223372036854775807" "9223372036854775807" ] [ "-42" "-42" ] ]"#
+    );
+    let printed = |expr: &str| try_eval(expr).and_then(|val| print_value(&val)).unwrap();
+    assert_eq!(
+        printed("[ 1.0 1.5 1.0e10 0.1 123456789.0 0.00001 1.0e-4 ]"),
+        "[ 1 1.5 1e+10 0.1 1.23457e+08 1e-05 0.0001 ]"
+    );
+    assert_eq!(
+        generated("keyValue", "{ ratio = 1.5; }"),
+        "ratio=1.500000\n"
+    );
+}
@@ -0,0 +1,83 @@
+mod common;
+use common::{eval, eval_err};
+
+#[test]
+fn curried_functions_thread_arguments() {
+    assert_eq!(eval("(a: b: c: [ a b c ]) 1 2 3"), "[ 1 2 3 ]");
+    assert_eq!(eval("(a: b: c: a + b + c) 1 2"), "«lambda»");
+    // Partial applications capture the arguments applied so far
+    assert_eq!(
+        eval("let f = a: b: c: [ a b c ]; g = f 1; h = g 2; in [ (h 3) (g 4 5) ]"),
+        "[ [ 1 2 3 ] [ 1 4 5 ] ]"
+    );
+    assert_eq!(
+        eval("let f = { x }: y: { z ? x + y }: z; in [ (f { x = 1; } 2 { }) (f { x = 1; } 2 { z = 0; }) ]"),
+        "[ 3 0 ]"
+    );
+}
+
+#[test]
+fn defaults_see_the_other_arguments_and_the_outer_scope() {
+    assert_eq!(
+        eval("let base = 10; f = { a ? b + base, b }: a; in [ (f { b = 1; }) (f { a = 0; b = 1; }) ]"),
+        "[ 11 0 ]"
+    );
+    // A default that isn't used is never evaluated
+    assert_eq!(
+        eval("({ a ? throw \"unused\", b }: b) { a = 1; b = 2; }"),
+        "2"
+    );
+}
+
+#[test]
+fn fixed_points() {
+    assert_eq!(
+        eval("lib.fix (self: { a = 1; b = self.a + 1; })"),
+        "{ a = 1; b = 2; }"
+    );
+    assert_eq!(eval("lib.fixedPoints.fix (self: [ 1 ])"), "[ 1 ]");
+    assert!(eval_err("lib.fix (self: self.a)").contains("infinite recursion"));
+    assert_eq!(
+        eval("lib.extends (self: super: { b = super.a + 1; }) (self: { a = 1; c = self.b; }) { b = 5; }"),
+        "{ a = 1; b = 2; c = 5; }"
+    );
+}
+
+#[test]
+fn extensible_sets() {
+    let set = "(lib.makeExtensible (self: { a = 1; b = self.a + 1; }))";
+    assert_eq!(eval(&format!("{}.b", set)), "2");
+    assert_eq!(
+        eval(&format!(
+            "let s = {}.extend (self: super: {{ a = 10; }}); in [ s.a s.b ]",
+            set
+        )),
+        "[ 10 11 ]"
+    );
+    assert_eq!(
+        eval(&format!(
+            "let s = ({}.extend (self: super: {{ a = 10; }})).extend (self: super: {{ c = super.b * 2; }}); in s.c",
+            set
+        )),
+        "22"
+    );
+}
+
+#[test]
+fn overridable_results() {
+    let pkg = r#"(lib.makeOverridable ({ name, version ? "1.0" }: { name = "${name}-${version}"; }) { name = "hello"; })"#;
+    assert_eq!(eval(&format!("{}.name", pkg)), r#""hello-1.0""#);
+    assert_eq!(
+        eval(&format!("({}.override {{ version = \"2.0\"; }}).name", pkg)),
+        r#""hello-2.0""#
+    );
+    // Overrides can be functions of the previous arguments, and overridden again
+    assert_eq!(
+        eval(&format!(
+            "(({}.override (old: {{ name = old.name + \"-wrapped\"; }})).override {{ version = \"3\"; }}).name",
+            pkg
+        )),
+        r#""hello-wrapped-3""#
+    );
+    assert_eq!(eval("lib.makeOverridable (x: x + 1) 1"), "2");
+}
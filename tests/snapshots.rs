@@ -0,0 +1,39 @@
+use rnix_experiments::{eval_str, print_value, EvalOptions};
+use std::path::Path;
+
+// Evaluates every fixture in tests/fixtures and compares the printed result, or the
+// error, with the .expected file next to it. Run with UPDATE_FIXTURES=1 to write the
+// current results instead
+#[test]
+fn fixtures_match_their_snapshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+    let mut fixtures = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nix"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    let mut mismatches = Vec::new();
+    for fixture in fixtures {
+        let source = std::fs::read_to_string(&fixture).unwrap();
+        let actual = match eval_str(&source, &EvalOptions::default()).and_then(|v| print_value(&v))
+        {
+            Ok(value) => value + "\n",
+            Err(err) => format!("error: {}\n", err),
+        };
+        let expected = fixture.with_extension("expected");
+        if update {
+            std::fs::write(&expected, &actual).unwrap();
+        } else if std::fs::read_to_string(&expected).ok().as_ref() != Some(&actual) {
+            mismatches.push(format!("{}:\n{}", fixture.display(), actual));
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "results differ from the snapshots:\n{}",
+        mismatches.join("\n")
+    );
+}